@@ -1,4 +1,5 @@
-use serde::{Deserialize, Serialize};
+use crate::helpers;
+use serde::Deserialize;
 use std::path::{Path, PathBuf};
 use std::{fmt, fs};
 
@@ -115,31 +116,84 @@ pub enum JsxMode {
     Automatic,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// The module that JSX is compiled to. Next to React, any module that implements the generic JSX
+/// interface can be used, like `"module": "Preact"`.
+#[derive(Debug, Clone, PartialEq)]
 pub enum JsxModule {
-    #[serde(rename = "react")]
     React,
+    Other(String),
+}
+
+impl<'de> Deserialize<'de> for JsxModule {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|module| match module.as_str() {
+            "react" => JsxModule::React,
+            _ => JsxModule::Other(module),
+        })
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
-pub enum Suffix {
-    #[serde(rename = ".js")]
-    Js,
-    #[serde(rename = ".mjs")]
-    Mjs,
-    #[serde(rename = ".cjs")]
-    Cjs,
-    #[serde(rename = ".bs.js")]
-    BsJs,
-    #[serde(rename = ".bs.mjs")]
-    BsMjs,
-    #[serde(rename = ".bs.cjs")]
-    BsCjs,
+impl fmt::Display for JsxModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsxModule::React => write!(f, "react"),
+            JsxModule::Other(module) => write!(f, "{}", module),
+        }
+    }
+}
+
+/// The suffix of the generated JavaScript files, including the leading dot. Next to the classic
+/// suffixes like `.mjs` and `.bs.js`, any suffix that ends in `.js`, `.mjs` or `.cjs` is allowed,
+/// like `.res.mjs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suffix(String);
+
+impl Suffix {
+    pub fn new(suffix: &str) -> Result<Self, String> {
+        let is_valid = suffix.starts_with('.')
+            && !suffix.contains(['/', '\\'])
+            && suffix[1..].split('.').all(|segment| !segment.is_empty())
+            && matches!(suffix.rsplit('.').next(), Some("js" | "mjs" | "cjs"));
+        if is_valid {
+            Ok(Suffix(suffix.to_string()))
+        } else {
+            Err(format!(
+                "invalid suffix `{}`, the suffix should start with a `.` and end with `.js`, `.mjs` or `.cjs`",
+                suffix
+            ))
+        }
+    }
+
+    /// The suffixes that every version of ReScript supports
+    pub fn is_classic(&self) -> bool {
+        matches!(
+            self.0.as_str(),
+            ".js" | ".mjs" | ".cjs" | ".bs.js" | ".bs.mjs" | ".bs.cjs"
+        )
+    }
+
+    /// The suffix without the leading dot, to use as a file extension
+    pub fn to_extension(&self) -> &str {
+        &self.0[1..]
+    }
+}
+
+impl Default for Suffix {
+    fn default() -> Self {
+        Suffix(".mjs".to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Suffix {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)
+            .and_then(|suffix| Suffix::new(&suffix).map_err(serde::de::Error::custom))
+    }
 }
 
 impl fmt::Display for Suffix {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", serde_json::to_value(self).unwrap().as_str().unwrap())
+        write!(f, "{}", self.0)
     }
 }
 
@@ -150,6 +204,57 @@ pub struct JsxSpecs {
     pub mode: Option<JsxMode>,
     #[serde(rename = "v3-dependencies")]
     pub v3_dependencies: Option<Vec<String>>,
+    /// Keep the JSX in the output instead of transforming it to function calls. This is only
+    /// supported from ReScript 12 on, with JSX version 4.
+    pub preserve: Option<bool>,
+}
+
+/// Settings that only apply to rewatch. This section is not part of the bsconfig.json spec, and
+/// is only read from the root package.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Rewatch {
+    /// The maximum number of compiler processes that run at the same time
+    pub jobs: Option<usize>,
+    /// The maximum number of compiler processes in watch mode. This is usually lower than `jobs`,
+    /// so the editor stays responsive while compiling.
+    #[serde(rename = "watch-jobs")]
+    pub watch_jobs: Option<usize>,
+    /// Layering rules that are checked against the dependencies between modules
+    pub boundaries: Option<Boundaries>,
+    /// The path to the bsc binary, relative to the root. By default it is found in the rescript
+    /// package in node_modules. A relative path given with `--bsc` or `RESCRIPT_BSC_EXE` is relative
+    /// to the current directory instead.
+    pub bsc: Option<String>,
+    /// A command that every invocation of bsc is wrapped in, like `ccache` for C compilers. The
+    /// arguments of the launcher are given as an array, like `["nice", "-n", "10"]`.
+    pub launcher: Option<OneOrMore<String>>,
+    /// A directory in the root (like `lib/rewatch`) to put the build artifacts of the dependencies
+    /// in, instead of their own folder. This is for when node_modules is read-only.
+    #[serde(rename = "build-dir")]
+    pub build_dir: Option<String>,
+    /// Print the packages in `bs-dependencies` that no module uses after collecting the
+    /// dependencies. This is off by default.
+    #[serde(rename = "report-unused-dependencies")]
+    pub report_unused_dependencies: Option<bool>,
+}
+
+/// Rules about which modules may use which other modules. Violations are build errors.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Boundaries {
+    /// Packages matching `from` may not depend on packages matching any of `disallow`
+    pub packages: Option<Vec<PackageBoundary>>,
+    /// Folders (relative to the package) of which the modules may only be used by modules in the
+    /// same folder, or its subfolders
+    #[serde(rename = "private-folders")]
+    pub private_folders: Option<Vec<String>>,
+}
+
+/// Package names are matched with glob patterns: `*` matches anything but a `/`, `**` matches
+/// anything, and `?` matches a single character.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PackageBoundary {
+    pub from: String,
+    pub disallow: Vec<String>,
 }
 
 /// # bsconfig.json representation
@@ -168,6 +273,14 @@ pub struct T {
     pub bs_dependencies: Option<Vec<String>>,
     #[serde(rename = "bs-dev-dependencies")]
     pub bs_dev_dependencies: Option<Vec<String>>,
+    // the ReScript 11 names of `bs-dependencies`, `bs-dev-dependencies` and `bsc-flags`. When
+    // parsing, these are moved into the legacy fields, so the rest of the build only has to look
+    // at those.
+    pub dependencies: Option<Vec<String>>,
+    #[serde(rename = "dev-dependencies")]
+    pub dev_dependencies: Option<Vec<String>>,
+    #[serde(rename = "compiler-flags")]
+    pub compiler_flags: Option<Vec<OneOrMore<String>>>,
     #[serde(rename = "ppx-flags")]
     pub ppx_flags: Option<Vec<OneOrMore<String>>>,
     #[serde(rename = "bsc-flags")]
@@ -182,6 +295,8 @@ pub struct T {
     // this is a new feature of rewatch, and it's not part of the bsconfig.json spec
     #[serde(rename = "allowed-dependents")]
     pub allowed_dependents: Option<Vec<String>>,
+    // this is a new feature of rewatch, and it's not part of the bsconfig.json spec
+    pub rewatch: Option<Rewatch>,
 }
 
 /// This flattens string flags
@@ -205,11 +320,19 @@ pub fn flatten_flags(flags: &Option<Vec<OneOrMore<String>>>) -> Vec<String> {
 }
 
 /// Since ppx-flags could be one or more, and could be nested potentiall, this function takes the
-/// flags and flattens them outright.
+/// flags and flattens them outright. Relative ppx paths are relative to the directory of the package.
+/// A ppx from a package is resolved from the directory of the package that uses it, like Node
+/// resolves packages. When it can't be found, we fall back to the `node_modules` of the root.
+fn resolve_ppx(ppx: &str, node_modules_dir: &str, package_dir: &str) -> String {
+    helpers::find_in_node_modules(ppx, package_dir)
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or(node_modules_dir.to_owned() + "/" + ppx)
+}
+
 pub fn flatten_ppx_flags(
     node_modules_dir: &String,
     flags: &Option<Vec<OneOrMore<String>>>,
-    package_name: &String,
+    package_dir: &String,
 ) -> Vec<String> {
     match flags {
         None => vec![],
@@ -220,20 +343,17 @@ pub fn flatten_ppx_flags(
                     let first_character = y.chars().nth(0);
                     match first_character {
                         Some('.') => {
-                            vec![
-                                "-ppx".to_string(),
-                                node_modules_dir.to_owned() + "/" + package_name + "/" + y,
-                            ]
+                            vec!["-ppx".to_string(), package_dir.to_owned() + "/" + y]
                         }
-                        _ => vec!["-ppx".to_string(), node_modules_dir.to_owned() + "/" + y],
+                        _ => vec!["-ppx".to_string(), resolve_ppx(y, node_modules_dir, package_dir)],
                     }
                 }
                 OneOrMore::Multiple(ys) if ys.len() == 0 => vec![],
                 OneOrMore::Multiple(ys) => {
                     let first_character = ys[0].chars().nth(0);
                     let ppx = match first_character {
-                        Some('.') => node_modules_dir.to_owned() + "/" + package_name + "/" + &ys[0],
-                        _ => node_modules_dir.to_owned() + "/" + &ys[0],
+                        Some('.') => package_dir.to_owned() + "/" + &ys[0],
+                        _ => resolve_ppx(&ys[0], node_modules_dir, package_dir),
                     };
                     vec![
                         "-ppx".to_string(),
@@ -250,12 +370,417 @@ pub fn flatten_ppx_flags(
     }
 }
 
-/// Try to convert a bsconfig from a certain path to a bsconfig struct
-pub fn read(path: String) -> T {
-    fs::read_to_string(path.clone())
-        .map_err(|e| format!("Could not read bsconfig. {path} - {e}"))
-        .and_then(|x| {
-            serde_json::from_str::<T>(&x).map_err(|e| format!("Could not parse bsconfig. {path} - {e}"))
-        })
-        .expect("Errors reading bsconfig")
+/// The fields we know about for every object in the config, by their path (without array indices).
+/// This is used to suggest a field when we find one we don't know. Objects that are parsed as one
+/// of multiple shapes (like `sources`) are not listed, because unknown fields in those are not
+/// reported by serde.
+fn get_known_fields(parent: &str) -> &'static [&'static str] {
+    match parent {
+        "" => &[
+            "name",
+            "sources",
+            "package-specs",
+            "warnings",
+            "suffix",
+            "pinned-dependencies",
+            "bs-dependencies",
+            "bs-dev-dependencies",
+            "dependencies",
+            "dev-dependencies",
+            "compiler-flags",
+            "ppx-flags",
+            "bsc-flags",
+            "reason",
+            "namespace",
+            "jsx",
+            "uncurried",
+            "namespace-entry",
+            "allowed-dependents",
+            "rewatch",
+        ],
+        "warnings" => &["number", "error"],
+        "reason" => &["react-jsx"],
+        "jsx" => &["version", "module", "mode", "v3-dependencies", "preserve"],
+        "rewatch" => &[
+            "jobs",
+            "watch-jobs",
+            "boundaries",
+            "bsc",
+            "launcher",
+            "build-dir",
+            "report-unused-dependencies",
+        ],
+        "rewatch.boundaries" => &["packages", "private-folders"],
+        "rewatch.boundaries.packages" => &["from", "disallow"],
+        _ => &[],
+    }
+}
+
+/// Fields that are part of the official config, but that rewatch doesn't use. We don't warn about
+/// these.
+const IGNORED_FIELDS: &[&str] = &[
+    "$schema",
+    "version",
+    "generators",
+    "cut-generators",
+    "external-stdlib",
+    "gentypeconfig",
+    "js-post-build",
+    "use-stdlib",
+    "bs-external-includes",
+];
+
+fn get_suggestion(field: &str, known_fields: &[&str]) -> Option<String> {
+    known_fields
+        .iter()
+        .map(|known_field| (strsim::osa_distance(field, known_field), known_field))
+        .filter(|(distance, _)| *distance <= field.len() / 3 + 1)
+        .min()
+        .map(|(_, known_field)| known_field.to_string())
+}
+
+/// Collects the keys (and not the array indices) of an ignored path, from the root to the field.
+fn get_path_keys(path: &serde_ignored::Path, keys: &mut Vec<String>) {
+    match path {
+        serde_ignored::Path::Root => (),
+        serde_ignored::Path::Seq { parent, .. }
+        | serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => get_path_keys(parent, keys),
+        serde_ignored::Path::Map { parent, key } => {
+            get_path_keys(parent, keys);
+            keys.push(key.to_owned());
+        }
+    }
+}
+
+/// The official build tool accepts comments and trailing commas in the config, so we do as well.
+/// They are replaced with whitespace, so the positions in parse errors still match the file.
+fn strip_comments_and_trailing_commas(contents: &str) -> String {
+    let mut bytes = contents.as_bytes().to_vec();
+    let mut i = 0;
+    let mut in_string = false;
+    let mut last_comma: Option<usize> = None;
+
+    while i < bytes.len() {
+        match (in_string, bytes[i], bytes.get(i + 1)) {
+            (true, b'\\', _) => i += 1,
+            (true, b'"', _) => in_string = false,
+            (true, _, _) => (),
+            (false, b'"', _) => {
+                in_string = true;
+                last_comma = None;
+            }
+            (false, b'/', Some(b'/')) => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    bytes[i] = b' ';
+                    i += 1;
+                }
+                continue;
+            }
+            (false, b'/', Some(b'*')) => {
+                let end = contents[i + 2..]
+                    .find("*/")
+                    .map(|end| i + 2 + end + 2)
+                    .unwrap_or(bytes.len());
+                bytes[i..end]
+                    .iter_mut()
+                    .filter(|byte| !byte.is_ascii_whitespace())
+                    .for_each(|byte| *byte = b' ');
+                i = end;
+                continue;
+            }
+            (false, b',', _) => last_comma = Some(i),
+            (false, b'}' | b']', _) => {
+                if let Some(comma) = last_comma {
+                    bytes[comma] = b' ';
+                }
+                last_comma = None;
+            }
+            (false, byte, _) if byte.is_ascii_whitespace() => (),
+            (false, _, _) => last_comma = None,
+        }
+        i += 1;
+    }
+
+    // we only replaced whole characters with spaces, so this is still valid utf-8
+    String::from_utf8(bytes).expect("Could not strip comments from config")
+}
+
+/// Parses the contents of a config file. Next to the config, this returns the warnings about the
+/// fields we don't know.
+pub fn parse(path: &str, contents: &str) -> Result<(T, Vec<String>), String> {
+    let contents = strip_comments_and_trailing_commas(contents);
+    let mut deserializer = serde_json::Deserializer::from_str(&contents);
+    let mut warnings = vec![];
+
+    let result: Result<T, _> = serde_path_to_error::deserialize(serde_ignored::Deserializer::new(
+        &mut deserializer,
+        &mut |ignored_path: serde_ignored::Path| {
+            let mut keys = vec![];
+            get_path_keys(&ignored_path, &mut keys);
+            let field = keys.pop().unwrap_or_default();
+            if keys.is_empty() && IGNORED_FIELDS.contains(&field.as_str()) {
+                return;
+            }
+            let suggestion = get_suggestion(&field, get_known_fields(&keys.join(".")))
+                .map(|suggestion| format!(", did you mean `{}`?", suggestion))
+                .unwrap_or_default();
+            keys.push(field);
+            warnings.push(format!(
+                "Unknown field `{}` in {}{}",
+                keys.join("."),
+                path,
+                suggestion
+            ));
+        },
+    ));
+
+    match result.map_err(|e| (e.path().to_string(), e.into_inner())) {
+        Ok(mut config) => {
+            deserializer.end().map_err(|e| format_error(path, None, &e))?;
+            normalize(path, &mut config, &mut warnings);
+            Ok((config, warnings))
+        }
+        Err((json_path, e)) => Err(format_error(path, Some(json_path), &e)),
+    }
+}
+
+/// Moves a setting from its ReScript 11 name into the legacy field. The new name takes precedence
+/// when both are set.
+fn normalize_field<V>(
+    path: &str,
+    new: (&str, Option<V>),
+    legacy: (&str, &mut Option<V>),
+    warnings: &mut Vec<String>,
+) {
+    let (new_name, new_value) = new;
+    let (legacy_name, legacy_value) = legacy;
+    if let Some(new_value) = new_value {
+        if legacy_value.is_some() {
+            warnings.push(format!(
+                "Both `{}` and `{}` are set in {}, using `{}`",
+                new_name, legacy_name, path, new_name
+            ));
+        }
+        *legacy_value = Some(new_value);
+    }
+}
+
+fn normalize(path: &str, config: &mut T, warnings: &mut Vec<String>) {
+    normalize_field(
+        path,
+        ("dependencies", config.dependencies.take()),
+        ("bs-dependencies", &mut config.bs_dependencies),
+        warnings,
+    );
+    normalize_field(
+        path,
+        ("dev-dependencies", config.dev_dependencies.take()),
+        ("bs-dev-dependencies", &mut config.bs_dev_dependencies),
+        warnings,
+    );
+    normalize_field(
+        path,
+        ("compiler-flags", config.compiler_flags.take()),
+        ("bsc-flags", &mut config.bsc_flags),
+        warnings,
+    );
+}
+
+fn format_error(path: &str, json_path: Option<String>, error: &serde_json::Error) -> String {
+    // the position is already part of the location, so we leave it out of the message
+    let message = error.to_string();
+    let message = message
+        .rsplit_once(" at line ")
+        .map(|(message, _)| message.to_string())
+        .unwrap_or(message);
+    match json_path {
+        Some(json_path) if json_path != "." => format!(
+            "Could not parse {}:{}:{} - at `{}`: {}",
+            path,
+            error.line(),
+            error.column(),
+            json_path,
+            message
+        ),
+        _ => format!(
+            "Could not parse {}:{}:{} - {}",
+            path,
+            error.line(),
+            error.column(),
+            message
+        ),
+    }
+}
+
+/// Try to convert a bsconfig from a certain path to a bsconfig struct. The warnings are returned
+/// instead of printed, because the same config is read several times per build.
+pub fn read(path: String) -> Result<(T, Vec<String>), String> {
+    let contents = fs::read_to_string(&path).map_err(|e| format!("Could not read {} - {}", path, e))?;
+    parse(&path, &contents)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_accepts_comments_and_trailing_commas() {
+        let (config, warnings) = parse(
+            "bsconfig.json",
+            r#"{
+                // the name of the package
+                "name": "my-package", /* with a "quoted" comment */
+                "sources": ["src", "tests",],
+                "bs-dependencies": ["https://example.com/a,b"],
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.name, "my-package");
+        assert_eq!(
+            config.bs_dependencies,
+            Some(vec!["https://example.com/a,b".to_string()])
+        );
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_reports_position_and_path() {
+        let error = parse(
+            "bsconfig.json",
+            "{\n  \"name\": \"my-package\",\n  \"sources\": \"src\",\n  \"jsx\": { \"version\": \"4\" }\n}",
+        )
+        .err()
+        .unwrap();
+        assert!(error.starts_with("Could not parse bsconfig.json:4:"), "{}", error);
+        assert!(error.contains("`jsx.version`"), "{}", error);
+    }
+
+    #[test]
+    fn test_parse_warns_about_unknown_fields() {
+        let (_, warnings) = parse(
+            "bsconfig.json",
+            r#"{"name": "my-package", "sources": "src", "bs-dependecies": [], "jsx": {"moduel": "react"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            warnings,
+            vec![
+                "Unknown field `bs-dependecies` in bsconfig.json, did you mean `bs-dependencies`?",
+                "Unknown field `jsx.moduel` in bsconfig.json, did you mean `module`?",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_prefers_rescript_11_field_names() {
+        let (config, warnings) = parse(
+            "rescript.json",
+            r#"{
+                "name": "my-package",
+                "sources": "src",
+                "dependencies": ["new-dependency"],
+                "bs-dependencies": ["old-dependency"],
+                "bs-dev-dependencies": ["dev-dependency"],
+                "compiler-flags": ["-open Belt"]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.bs_dependencies, Some(vec!["new-dependency".to_string()]));
+        assert_eq!(
+            config.bs_dev_dependencies,
+            Some(vec!["dev-dependency".to_string()])
+        );
+        assert_eq!(flatten_flags(&config.bsc_flags), vec!["-open", "Belt"]);
+        assert_eq!(
+            warnings,
+            vec!["Both `dependencies` and `bs-dependencies` are set in rescript.json, using `dependencies`"]
+        );
+    }
+
+    #[test]
+    fn test_suffix_validation() {
+        for suffix in [".js", ".mjs", ".cjs", ".bs.js", ".res.js", ".res.mjs"] {
+            assert_eq!(
+                Suffix::new(suffix).map(|suffix| suffix.to_string()),
+                Ok(suffix.to_string())
+            );
+        }
+        for suffix in ["js", ".ts", ".", "..js", ".res/js", ".xjs"] {
+            assert!(Suffix::new(suffix).is_err(), "{}", suffix);
+        }
+        assert_eq!(Suffix::new(".res.mjs").unwrap().to_extension(), "res.mjs");
+
+        let error = parse(
+            "bsconfig.json",
+            r#"{"name": "my-package", "sources": "src", "suffix": ".ts"}"#,
+        )
+        .err()
+        .unwrap();
+        assert!(error.contains("invalid suffix `.ts`"), "{}", error);
+    }
+
+    #[test]
+    fn test_parse_boundaries() {
+        let (config, warnings) = parse(
+            "bsconfig.json",
+            r#"{
+                "name": "my-package",
+                "sources": "src",
+                "rewatch": {
+                    "boundaries": {
+                        "packages": [{"from": "@org/ui-*", "disallow": ["@org/server-*"]}],
+                        "private-folders": ["src/internal"],
+                        "privat-folders": []
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let boundaries = config.rewatch.unwrap().boundaries.unwrap();
+        let packages = boundaries.packages.unwrap();
+        assert_eq!(packages[0].from, "@org/ui-*");
+        assert_eq!(packages[0].disallow, vec!["@org/server-*".to_string()]);
+        assert_eq!(boundaries.private_folders, Some(vec!["src/internal".to_string()]));
+        assert_eq!(
+            warnings,
+            vec![
+                "Unknown field `rewatch.boundaries.privat-folders` in bsconfig.json, did you mean \
+                 `private-folders`?"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unused_dependencies_are_not_reported_by_default() {
+        let (config, warnings) = parse(
+            "bsconfig.json",
+            r#"{"name": "my-package", "sources": "src", "rewatch": {"jobs": 2}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.rewatch.unwrap().report_unused_dependencies, None);
+        assert!(warnings.is_empty());
+
+        let (config, warnings) = parse(
+            "bsconfig.json",
+            r#"{"name": "my-package", "sources": "src", "rewatch": {"report-unused-dependencies": true}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.rewatch.unwrap().report_unused_dependencies, Some(true));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_launcher_with_arguments() {
+        let (config, _) = parse(
+            "bsconfig.json",
+            r#"{"name": "my-package", "sources": "src", "rewatch": {"launcher": ["nice", "-n", "10"]}}"#,
+        )
+        .unwrap();
+        match config.rewatch.unwrap().launcher {
+            Some(OneOrMore::Multiple(launcher)) => assert_eq!(launcher, vec!["nice", "-n", "10"]),
+            launcher => panic!("Expected a launcher with arguments, got {:?}", launcher),
+        }
+    }
 }
@@ -1,12 +1,17 @@
+pub mod boundaries;
 pub mod build_types;
 pub mod clean;
 pub mod compile;
+pub mod compiler;
 pub mod deps;
+pub mod fingerprint;
+pub mod jobs;
 pub mod logs;
 pub mod namespaces;
 pub mod packages;
 pub mod parse;
 pub mod read_compile_state;
+pub mod version;
 
 use crate::helpers;
 use crate::helpers::emojis::*;
@@ -14,21 +19,9 @@ use build_types::*;
 use console::style;
 use indicatif::{ProgressBar, ProgressStyle};
 use std::io::{stdout, Write};
-use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Instant;
 
-pub fn get_version(project_root: &str) -> String {
-    let version_cmd = Command::new(helpers::get_bsc(&project_root))
-        .args(["-v"])
-        .output()
-        .expect("failed to find version");
-
-    std::str::from_utf8(&version_cmd.stdout)
-        .expect("Could not read version from rescript")
-        .replace("\n", "")
-        .replace("ReScript ", "")
-}
-
 fn is_dirty(module: &Module) -> bool {
     match module.source_type {
         SourceType::SourceFile(SourceFile {
@@ -44,11 +37,38 @@ fn is_dirty(module: &Module) -> bool {
     }
 }
 
-pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Result<BuildState, ()> {
+/// Stops the build after the running compiler processes have finished. The files that were not
+/// parsed or compiled yet stay dirty, so the next build picks them up.
+fn cancel(build_state: &BuildState) -> Result<BuildState, ()> {
+    logs::finalize(&build_state.packages);
+    clean::cleanup_after_build(build_state);
+    println!(
+        "{}\r{} {}Cancelled the build, files changed while building",
+        LINE_CLEAR,
+        style("[...]").bold().dim(),
+        CROSS,
+    );
+    Err(())
+}
+
+pub fn build(
+    filter: &Option<regex::Regex>,
+    path: &str,
+    focus: &Option<String>,
+    compiler: &compiler::Compiler,
+    no_timing: bool,
+    keep_going: bool,
+    cancelled: &AtomicBool,
+) -> Result<BuildState, ()> {
     let timing_total = Instant::now();
     let project_root = helpers::get_abs_path(path);
-    let rescript_version = get_version(&project_root);
-    let root_config_name = packages::get_package_name(&project_root);
+    let rescript_version = match version::get_version(compiler) {
+        Ok(rescript_version) => rescript_version,
+        Err(e) => {
+            println!("{}", style(e).red());
+            return Err(());
+        }
+    };
     let default_timing: Option<std::time::Duration> = if no_timing {
         Some(std::time::Duration::new(0.0 as u64, 0.0 as u32))
     } else {
@@ -62,7 +82,27 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
     );
     let _ = stdout().flush();
     let timing_package_tree = Instant::now();
-    let packages = packages::make(&filter, &project_root);
+    let (root_config_name, packages) = match packages::get_package_name(&project_root)
+        .and_then(|root_config_name| Ok((root_config_name, packages::make(&filter, &project_root)?)))
+        .and_then(|(root_config_name, packages)| {
+            packages::validate_focus(&packages, focus, &root_config_name)?;
+            Ok((root_config_name, packages))
+        }) {
+        Ok(result) => result,
+        Err(e) => {
+            println!(
+                "{}\r{} {}Error building package tree in {:.2}s",
+                LINE_CLEAR,
+                style("[1/7]").bold().dim(),
+                CROSS,
+                default_timing
+                    .unwrap_or(timing_package_tree.elapsed())
+                    .as_secs_f64()
+            );
+            println!("{}", style(e).red());
+            return Err(());
+        }
+    };
     let timing_package_tree_elapsed = timing_package_tree.elapsed();
 
     println!(
@@ -78,7 +118,22 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
     if !packages::validate_packages_dependencies(&packages) {
         return Err(());
     }
-    
+
+    if let Some(root_package) = packages.get(&root_config_name) {
+        let mut config_errors = packages
+            .values()
+            .filter_map(|package| package.validate_jsx(&rescript_version, root_package).err())
+            .chain(root_package.validate_suffix(&rescript_version).err())
+            .collect::<Vec<String>>();
+        if !config_errors.is_empty() {
+            config_errors.sort();
+            config_errors
+                .iter()
+                .for_each(|e| println!("\n{}: {}", style("Error").red(), e));
+            return Err(());
+        }
+    }
+
     let timing_source_files = Instant::now();
 
     print!(
@@ -87,9 +142,26 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
         LOOKING_GLASS
     );
     let _ = stdout().flush();
-    let mut build_state = BuildState::new(project_root, root_config_name, packages);
+    let mut build_state = BuildState::new(project_root, root_config_name, packages, compiler.to_owned());
+    let module_name_collisions = packages::get_module_name_collisions(&build_state.packages);
+    if !module_name_collisions.is_empty() {
+        println!(
+            "{}\r{} {}Error finding source files in {:.2}s",
+            LINE_CLEAR,
+            style("[2/7]").bold().dim(),
+            CROSS,
+            default_timing
+                .unwrap_or(timing_source_files.elapsed())
+                .as_secs_f64()
+        );
+        print!(
+            "{}",
+            packages::format_module_name_collisions(&module_name_collisions)
+        );
+        return Err(());
+    }
     packages::parse_packages(&mut build_state);
-    logs::initialize(&build_state.project_root, &build_state.packages);
+    logs::initialize(&build_state.packages);
     let timing_source_files_elapsed = timing_source_files.elapsed();
     println!(
         "{}\r{} {}Found source files in {:.2}s",
@@ -110,6 +182,11 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
     let compile_assets_state = read_compile_state::read(&mut build_state);
     let (diff_cleanup, total_cleanup, deleted_module_names) =
         clean::cleanup_previous_build(&mut build_state, compile_assets_state);
+    let fingerprints = fingerprint::get_fingerprints(&build_state, &rescript_version);
+    fingerprint::mark_changed_packages_dirty(&mut build_state, &fingerprints);
+    if let Some(focus) = focus {
+        packages::retain_focus_modules(&mut build_state, focus);
+    }
     let timing_cleanup_elapsed = timing_cleanup.elapsed();
     println!(
         "{}\r{} {}Cleaned {}/{} {:.2}s",
@@ -134,8 +211,9 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
     );
 
     let timing_ast = Instant::now();
-    let result_asts = parse::generate_asts(&rescript_version, &mut build_state, || pb.inc(1));
+    let result_asts = parse::generate_asts(&rescript_version, &mut build_state, cancelled, || pb.inc(1));
     let timing_ast_elapsed = timing_ast.elapsed();
+    let mut has_parse_errors = false;
 
     match result_asts {
         Ok(err) => {
@@ -149,8 +227,21 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
             );
             print!("{}", &err);
         }
+        // with keep going, we still compile all the modules that don't depend on a module that
+        // failed to parse
+        Err(err) if keep_going => {
+            has_parse_errors = true;
+            println!(
+                "{}\r{} {}Error parsing source files in {:.2}s",
+                LINE_CLEAR,
+                style("[4/7]").bold().dim(),
+                CROSS,
+                default_timing.unwrap_or(timing_ast_elapsed).as_secs_f64()
+            );
+            print!("{}", &err);
+        }
         Err(err) => {
-            logs::finalize(&build_state.project_root, &build_state.packages);
+            logs::finalize(&build_state.packages);
             println!(
                 "{}\r{} {}Error parsing source files in {:.2}s",
                 LINE_CLEAR,
@@ -164,10 +255,65 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
         }
     }
 
+    if cancelled.load(Ordering::Relaxed) {
+        return cancel(&build_state);
+    }
+
     let timing_deps = Instant::now();
     deps::get_deps(&mut build_state, &deleted_module_names);
+    let cycles = compile::dependency_cycle::find(&build_state.modules);
     let timing_deps_elapsed = timing_deps.elapsed();
 
+    if !cycles.is_empty() {
+        logs::finalize(&build_state.packages);
+        println!(
+            "{}\r{} {}Error collecting deps in {:.2}s",
+            LINE_CLEAR,
+            style("[5/7]").bold().dim(),
+            CROSS,
+            default_timing.unwrap_or(timing_deps_elapsed).as_secs_f64()
+        );
+        print!(
+            "{}",
+            compile::dependency_cycle::format_error(&cycles, &build_state)
+        );
+        clean::cleanup_after_build(&build_state);
+        return Err(());
+    }
+
+    let undeclared_dependencies = deps::get_undeclared_dependencies(&build_state);
+    if !undeclared_dependencies.is_empty() {
+        logs::finalize(&build_state.packages);
+        println!(
+            "{}\r{} {}Error collecting deps in {:.2}s",
+            LINE_CLEAR,
+            style("[5/7]").bold().dim(),
+            CROSS,
+            default_timing.unwrap_or(timing_deps_elapsed).as_secs_f64()
+        );
+        print!(
+            "{}",
+            deps::format_undeclared_dependencies(&undeclared_dependencies)
+        );
+        clean::cleanup_after_build(&build_state);
+        return Err(());
+    }
+
+    let boundary_violations = boundaries::get_violations(&build_state);
+    if !boundary_violations.is_empty() {
+        logs::finalize(&build_state.packages);
+        println!(
+            "{}\r{} {}Error collecting deps in {:.2}s",
+            LINE_CLEAR,
+            style("[5/7]").bold().dim(),
+            CROSS,
+            default_timing.unwrap_or(timing_deps_elapsed).as_secs_f64()
+        );
+        print!("{}", boundaries::format_violations(&boundary_violations));
+        clean::cleanup_after_build(&build_state);
+        return Err(());
+    }
+
     println!(
         "{}\r{} {}Collected deps in {:.2}s",
         LINE_CLEAR,
@@ -175,6 +321,17 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
         CHECKMARK,
         default_timing.unwrap_or(timing_deps_elapsed).as_secs_f64()
     );
+    let report_unused_dependencies = build_state
+        .get_package(&build_state.root_config_name)
+        .and_then(|root_package| root_package.bsconfig.rewatch.to_owned())
+        .and_then(|rewatch| rewatch.report_unused_dependencies)
+        .unwrap_or(false);
+    if report_unused_dependencies {
+        let unused_dependencies = deps::get_unused_dependencies(&build_state);
+        if !unused_dependencies.is_empty() {
+            print!("{}", deps::format_unused_dependencies(&unused_dependencies));
+        }
+    }
 
     let start_compiling = Instant::now();
     let pb = ProgressBar::new(build_state.modules.len().try_into().unwrap());
@@ -190,13 +347,18 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
         &mut build_state,
         &deleted_module_names,
         &rescript_version,
+        keep_going,
+        cancelled,
         || pb.inc(1),
         |size| pb.set_length(size),
     );
     let compile_duration = start_compiling.elapsed();
 
-    logs::finalize(&build_state.project_root, &build_state.packages);
     pb.finish();
+    if cancelled.load(Ordering::Relaxed) {
+        return cancel(&build_state);
+    }
+    logs::finalize(&build_state.packages);
     clean::cleanup_after_build(&build_state);
     if compile_errors.len() > 0 {
         if helpers::contains_ascii_characters(&compile_warnings) {
@@ -224,6 +386,15 @@ pub fn build(filter: &Option<regex::Regex>, path: &str, no_timing: bool) -> Resu
         if helpers::contains_ascii_characters(&compile_warnings) {
             print!("{}", &compile_warnings);
         }
+        if has_parse_errors {
+            return Err(());
+        }
+        clean::write_suffix_markers(&build_state);
+        fingerprint::write_fingerprints(&build_state, &fingerprints);
+        // with a filter, the source files of the dependencies are incomplete
+        if filter.is_none() {
+            packages::write_prebuilt_manifests(&build_state);
+        }
     }
 
     let timing_total_elapsed = timing_total.elapsed();
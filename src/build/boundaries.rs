@@ -0,0 +1,352 @@
+use super::build_types::*;
+use crate::bsconfig;
+use crate::helpers;
+use console::style;
+use std::path::Path;
+
+/// Matches a glob pattern against a package name or path. `*` matches any number of characters
+/// except `/`, `**` matches any number of characters (and `**/` any number of folders), and `?`
+/// matches a single character except `/`.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[u8], text: &[u8]) -> bool {
+        match pattern {
+            [] => text.is_empty(),
+            // `**/` also matches no folder at all, so `src/**/internal` matches `src/internal`
+            [b'*', b'*', b'/', rest @ ..] => (0..=text.len())
+                .filter(|i| *i == 0 || text[i - 1] == b'/')
+                .any(|i| matches(rest, &text[i..])),
+            [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| matches(rest, &text[i..])),
+            [b'*', rest @ ..] => (0..=text.len())
+                .take_while(|i| *i == 0 || text[i - 1] != b'/')
+                .any(|i| matches(rest, &text[i..])),
+            [b'?', rest @ ..] => match text {
+                [c, text @ ..] if *c != b'/' => matches(rest, text),
+                _ => false,
+            },
+            [p, rest @ ..] => match text {
+                [c, text @ ..] if c == p => matches(rest, text),
+                _ => false,
+            },
+        }
+    }
+    matches(pattern.as_bytes(), text.as_bytes())
+}
+
+/// A dependency between two modules that breaks one of the boundary rules.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BoundaryViolation {
+    pub module_name: String,
+    pub dependency_module_name: String,
+    pub rule: String,
+}
+
+fn get_module_path(module: &Module) -> Option<&str> {
+    match &module.source_type {
+        SourceType::SourceFile(source_file) => Some(
+            source_file
+                .implementation
+                .path
+                .strip_prefix("./")
+                .unwrap_or(&source_file.implementation.path),
+        ),
+        SourceType::MlMap(_) => None,
+    }
+}
+
+/// The innermost folder of the module that matches one of the private folder patterns.
+fn get_private_folder<'a>(path: &'a str, private_folders: &[String]) -> Option<&'a str> {
+    Path::new(path)
+        .ancestors()
+        .skip(1)
+        .filter_map(|folder| folder.to_str())
+        .filter(|folder| !folder.is_empty())
+        .find(|folder| private_folders.iter().any(|pattern| glob_match(pattern, folder)))
+}
+
+fn get_package_violation(
+    package_boundaries: &[bsconfig::PackageBoundary],
+    package_name: &str,
+    dependency_package_name: &str,
+) -> Option<String> {
+    package_boundaries.iter().find_map(|boundary| {
+        if !glob_match(&boundary.from, package_name) {
+            return None;
+        }
+        boundary
+            .disallow
+            .iter()
+            .find(|disallowed| glob_match(disallowed, dependency_package_name))
+            .map(|disallowed| {
+                format!(
+                    "{} ({}) may not depend on {} ({})",
+                    package_name, boundary.from, dependency_package_name, disallowed
+                )
+            })
+    })
+}
+
+fn get_private_folder_violation(
+    private_folders: &[String],
+    module: &Module,
+    dependency_module: &Module,
+) -> Option<String> {
+    let dependency_path = get_module_path(dependency_module)?;
+    let private_folder = get_private_folder(dependency_path, private_folders)?;
+    let is_inside = module.package_name == dependency_module.package_name
+        && get_module_path(module)
+            .map(|path| Path::new(path).starts_with(private_folder))
+            .unwrap_or(false);
+    if is_inside {
+        None
+    } else {
+        Some(format!(
+            "{} in {} is private to {}",
+            dependency_path, dependency_module.package_name, private_folder
+        ))
+    }
+}
+
+/// Checks every dependency between modules against the boundary rules in the `rewatch` section of
+/// the root config.
+pub fn get_violations(build_state: &BuildState) -> Vec<BoundaryViolation> {
+    let boundaries = build_state
+        .get_package(&build_state.root_config_name)
+        .and_then(|root_package| root_package.bsconfig.rewatch.to_owned())
+        .and_then(|rewatch| rewatch.boundaries)
+        .unwrap_or_default();
+    let package_boundaries = boundaries.packages.unwrap_or_default();
+    let private_folders = boundaries.private_folders.unwrap_or_default();
+    if package_boundaries.is_empty() && private_folders.is_empty() {
+        return vec![];
+    }
+
+    let mut violations = build_state
+        .modules
+        .iter()
+        .flat_map(|(module_name, module)| {
+            let package_boundaries = &package_boundaries;
+            let private_folders = &private_folders;
+            module.deps.iter().filter_map(move |dep| {
+                let dependency_module = build_state.get_module(dep)?;
+                let rule = if module.package_name != dependency_module.package_name {
+                    get_package_violation(
+                        package_boundaries,
+                        &module.package_name,
+                        &dependency_module.package_name,
+                    )
+                } else {
+                    None
+                }
+                .or_else(|| get_private_folder_violation(private_folders, module, dependency_module))?;
+                Some(BoundaryViolation {
+                    module_name: module_name.to_owned(),
+                    dependency_module_name: dep.to_owned(),
+                    rule,
+                })
+            })
+        })
+        .collect::<Vec<BoundaryViolation>>();
+    violations.sort();
+    violations
+}
+
+pub fn format_violations(violations: &[BoundaryViolation]) -> String {
+    format!(
+        "\n{}\n{}\n",
+        style("Can't continue... Found dependencies that break the boundaries in the rewatch config:").red(),
+        violations
+            .iter()
+            .map(|violation| {
+                format!(
+                    "  {} -> {}: {}",
+                    helpers::format_namespaced_module_name(&violation.module_name),
+                    helpers::format_namespaced_module_name(&violation.dependency_module_name),
+                    violation.rule
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_private_folder, get_violations, glob_match, BoundaryViolation};
+    use crate::build::build_types::*;
+    use crate::build::compiler::Compiler;
+    use crate::build::packages::{Namespace, Package};
+    use ahash::{AHashMap, AHashSet};
+    use std::time::SystemTime;
+
+    fn create_package(name: &str, bsconfig: &str) -> Package {
+        Package {
+            name: name.to_string(),
+            bsconfig: serde_json::from_str(bsconfig).unwrap(),
+            source_folders: AHashSet::new(),
+            source_files: None,
+            namespace: Namespace::NoNamespace,
+            modules: None,
+            package_dir: format!("/project/node_modules/{}", name),
+            build_dir: format!("/project/node_modules/{}", name),
+            dirs: None,
+            is_pinned_dep: false,
+            is_root: false,
+        }
+    }
+
+    fn create_module(package_name: &str, path: &str, deps: &[&str]) -> Module {
+        Module {
+            source_type: SourceType::SourceFile(SourceFile {
+                implementation: Implementation {
+                    path: path.to_string(),
+                    parse_state: ParseState::Success,
+                    compile_state: CompileState::Pending,
+                    last_modified: SystemTime::now(),
+                    dirty: true,
+                },
+                interface: None,
+            }),
+            deps: deps.iter().map(|dep| dep.to_string()).collect(),
+            dependents: AHashSet::new(),
+            package_name: package_name.to_string(),
+            compile_dirty: true,
+            last_compiled_cmi: None,
+            last_compiled_cmt: None,
+            last_compiled_cmj: None,
+        }
+    }
+
+    /// `@org/ui-*` packages may not use `@org/server-*` packages, and the modules in `src/internal`
+    /// are private.
+    fn build_state(modules: Vec<(&str, Module)>) -> BuildState {
+        let packages = [
+            create_package(
+                "root",
+                r#"{
+                    "name": "root",
+                    "sources": "src",
+                    "rewatch": {
+                        "boundaries": {
+                            "packages": [{"from": "@org/ui-*", "disallow": ["@org/server-*"]}],
+                            "private-folders": ["src/internal"]
+                        }
+                    }
+                }"#,
+            ),
+            create_package(
+                "@org/ui-button",
+                r#"{"name": "@org/ui-button", "sources": "src"}"#,
+            ),
+            create_package(
+                "@org/server-api",
+                r#"{"name": "@org/server-api", "sources": "src"}"#,
+            ),
+            create_package("@org/utils", r#"{"name": "@org/utils", "sources": "src"}"#),
+        ]
+        .into_iter()
+        .map(|package| (package.name.to_owned(), package))
+        .collect::<AHashMap<String, Package>>();
+        let mut build_state = BuildState::new(
+            String::from("/project"),
+            String::from("root"),
+            packages,
+            Compiler::default(),
+        );
+        for (module_name, module) in modules {
+            build_state.insert_module(module_name, module);
+        }
+        build_state
+    }
+
+    #[test]
+    fn test_allowed_dependencies_are_no_violations() {
+        let build_state = build_state(vec![
+            (
+                "Button",
+                create_module("@org/ui-button", "src/Button.res", &["Utils"]),
+            ),
+            ("Utils", create_module("@org/utils", "src/Utils.res", &["Api"])),
+            ("Api", create_module("@org/server-api", "src/Api.res", &[])),
+            (
+                "Queries",
+                create_module("@org/server-api", "src/internal/Queries.res", &["Helpers"]),
+            ),
+            (
+                "Helpers",
+                create_module("@org/server-api", "src/internal/Helpers.res", &[]),
+            ),
+        ]);
+        assert_eq!(get_violations(&build_state), vec![]);
+    }
+
+    #[test]
+    fn test_dependency_on_a_disallowed_package_is_a_violation() {
+        let build_state = build_state(vec![
+            (
+                "Button",
+                create_module("@org/ui-button", "src/Button.res", &["Api"]),
+            ),
+            ("Api", create_module("@org/server-api", "src/Api.res", &[])),
+        ]);
+        assert_eq!(
+            get_violations(&build_state),
+            vec![BoundaryViolation {
+                module_name: String::from("Button"),
+                dependency_module_name: String::from("Api"),
+                rule: String::from(
+                    "@org/ui-button (@org/ui-*) may not depend on @org/server-api (@org/server-*)"
+                ),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_dependency_on_a_private_folder_of_another_package_is_a_violation() {
+        let build_state = build_state(vec![
+            (
+                "Button",
+                create_module("@org/ui-button", "src/internal/Button.res", &["Helpers"]),
+            ),
+            (
+                "Helpers",
+                create_module("@org/utils", "src/internal/Helpers.res", &[]),
+            ),
+        ]);
+        assert_eq!(
+            get_violations(&build_state),
+            vec![BoundaryViolation {
+                module_name: String::from("Button"),
+                dependency_module_name: String::from("Helpers"),
+                rule: String::from("src/internal/Helpers.res in @org/utils is private to src/internal"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("@org/ui-*", "@org/ui-button"));
+        assert!(!glob_match("@org/ui-*", "@org/server-api"));
+        assert!(!glob_match("@org/*", "@org/ui/nested"));
+        assert!(glob_match("@org/**", "@org/ui/nested"));
+        assert!(glob_match("src/**/internal", "src/internal"));
+        assert!(glob_match("src/**/internal", "src/a/b/internal"));
+        assert!(glob_match("lib-?", "lib-a"));
+        assert!(!glob_match("lib-?", "lib-ab"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+    }
+
+    #[test]
+    fn test_private_folder_is_the_innermost_matching_folder() {
+        let private_folders = vec!["src/internal".to_string(), "**/private".to_string()];
+        assert_eq!(
+            get_private_folder("src/internal/Foo.res", &private_folders),
+            Some("src/internal")
+        );
+        assert_eq!(
+            get_private_folder("src/internal/private/Foo.res", &private_folders),
+            Some("src/internal/private")
+        );
+        assert_eq!(get_private_folder("src/Foo.res", &private_folders), None);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::build::compiler::Compiler;
 use crate::build::packages::{Namespace, Package};
 use ahash::{AHashMap, AHashSet};
 use std::time::SystemTime;
@@ -61,6 +62,7 @@ pub struct Module {
     pub compile_dirty: bool,
     pub last_compiled_cmi: Option<SystemTime>,
     pub last_compiled_cmt: Option<SystemTime>,
+    pub last_compiled_cmj: Option<SystemTime>,
 }
 
 impl Module {
@@ -70,6 +72,19 @@ impl Module {
             _ => false,
         }
     }
+    pub fn has_parse_error(&self) -> bool {
+        match &self.source_type {
+            SourceType::SourceFile(source_file) => {
+                source_file.implementation.parse_state == ParseState::ParseError
+                    || source_file
+                        .interface
+                        .as_ref()
+                        .map(|interface| interface.parse_state == ParseState::ParseError)
+                        .unwrap_or(false)
+            }
+            SourceType::MlMap(_) => false,
+        }
+    }
     pub fn get_interface<'a>(&'a self) -> &'a Option<Interface> {
         match &self.source_type {
             SourceType::SourceFile(source_file) => &source_file.interface,
@@ -85,6 +100,7 @@ pub struct BuildState {
     pub module_names: AHashSet<String>,
     pub project_root: String,
     pub root_config_name: String,
+    pub compiler: Compiler,
 }
 
 impl BuildState {
@@ -95,13 +111,34 @@ impl BuildState {
     pub fn get_module(&self, module_name: &str) -> Option<&Module> {
         self.modules.get(module_name)
     }
-    pub fn new(project_root: String, root_config_name: String, packages: AHashMap<String, Package>) -> Self {
+
+    /// The packages that have modules in this build. When the build is focused on a package, the
+    /// modules of the packages outside of the focus are left out (see
+    /// `packages::retain_focus_modules`), so their artifacts are not up-to-date.
+    pub fn get_built_packages(&self) -> Vec<&Package> {
+        let package_names = self
+            .modules
+            .values()
+            .map(|module| module.package_name.as_str())
+            .collect::<AHashSet<&str>>();
+        self.packages
+            .values()
+            .filter(|package| package_names.contains(package.name.as_str()))
+            .collect()
+    }
+    pub fn new(
+        project_root: String,
+        root_config_name: String,
+        packages: AHashMap<String, Package>,
+        compiler: Compiler,
+    ) -> Self {
         Self {
             module_names: AHashSet::new(),
             modules: AHashMap::new(),
             packages: packages,
             project_root: project_root,
             root_config_name: root_config_name,
+            compiler,
         }
     }
     pub fn insert_module(&mut self, module_name: &str, module: Module) {
@@ -116,14 +153,17 @@ pub struct AstModule {
     pub namespace: Namespace,
     pub last_modified: SystemTime,
     pub ast_file_path: String,
-    pub is_root: bool,
-    pub suffix: Option<crate::bsconfig::Suffix>,
+    pub package_dir: String,
+    pub build_dir: String,
+    /// The suffix that the JavaScript file of this module was last generated with
+    pub suffix: crate::bsconfig::Suffix,
 }
 
 pub struct CompileAssetsState {
     pub ast_modules: AHashMap<String, AstModule>,
     pub cmi_modules: AHashMap<String, SystemTime>,
     pub cmt_modules: AHashMap<String, SystemTime>,
+    pub cmj_modules: AHashMap<String, SystemTime>,
     pub ast_rescript_file_locations: AHashSet<String>,
     pub rescript_file_locations: AHashSet<String>,
 }
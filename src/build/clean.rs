@@ -1,5 +1,7 @@
 use super::build_types::*;
+use super::compiler::Compiler;
 use super::packages;
+use super::parse;
 use crate::bsconfig;
 use crate::helpers;
 use crate::helpers::emojis::*;
@@ -7,86 +9,66 @@ use ahash::AHashSet;
 use console::style;
 use rayon::prelude::*;
 use std::io::Write;
+use std::path::Path;
 use std::time::Instant;
 
-fn remove_ast(source_file: &str, package_name: &str, root_path: &str, is_root: bool) {
-    let _ = std::fs::remove_file(helpers::get_compiler_asset(
-        source_file,
-        package_name,
-        &packages::Namespace::NoNamespace,
-        root_path,
-        "ast",
-        is_root,
-    ));
+fn remove_ast(source_file: &str, build_dir: &str) {
+    let ast_path = helpers::get_ast_path(source_file, build_dir);
+    let _ = std::fs::remove_file(helpers::get_ast_digest_path(&ast_path));
+    let _ = std::fs::remove_file(ast_path);
 }
 
-fn remove_iast(source_file: &str, package_name: &str, root_path: &str, is_root: bool) {
-    let _ = std::fs::remove_file(helpers::get_compiler_asset(
-        source_file,
-        package_name,
-        &packages::Namespace::NoNamespace,
-        root_path,
-        "iast",
-        is_root,
-    ));
+fn remove_iast(source_file: &str, build_dir: &str) {
+    let iast_path = helpers::get_iast_path(source_file, build_dir);
+    let _ = std::fs::remove_file(helpers::get_ast_digest_path(&iast_path));
+    let _ = std::fs::remove_file(iast_path);
+}
+
+/// The JavaScript file of a module is written next to its source file. For a package that is built
+/// out of tree, it is written to the same place in the build dir.
+fn get_mjs_file_location(res_file_location: &str, package_dir: &str, build_dir: &str) -> String {
+    match Path::new(res_file_location).strip_prefix(package_dir) {
+        Ok(relative_path) if package_dir != build_dir => Path::new(build_dir)
+            .join(relative_path)
+            .to_string_lossy()
+            .to_string(),
+        _ => res_file_location.to_string(),
+    }
 }
 
 fn remove_mjs_file(source_file: &str, suffix: &bsconfig::Suffix) {
-    let _ = std::fs::remove_file(helpers::change_extension(
-        source_file,
-        // suffix.to_string includes the ., so we need to remove it
-        &suffix.to_string()[1..],
-    ));
+    let _ = std::fs::remove_file(helpers::change_extension(source_file, suffix.to_extension()));
 }
 
 fn remove_compile_asset(
     source_file: &str,
-    package_name: &str,
     namespace: &packages::Namespace,
-    root_path: &str,
-    is_root: bool,
+    build_dir: &str,
     extension: &str,
 ) {
     let _ = std::fs::remove_file(helpers::get_compiler_asset(
         source_file,
-        package_name,
         namespace,
-        root_path,
+        build_dir,
         extension,
-        is_root,
     ));
     let _ = std::fs::remove_file(helpers::get_bs_compiler_asset(
         source_file,
-        package_name,
         namespace,
-        root_path,
+        build_dir,
         extension,
-        is_root,
     ));
 }
 
-pub fn remove_compile_assets(
-    source_file: &str,
-    package_name: &str,
-    namespace: &packages::Namespace,
-    root_path: &str,
-    is_root: bool,
-) {
+pub fn remove_compile_assets(source_file: &str, namespace: &packages::Namespace, build_dir: &str) {
     // optimization
     // only issue cmti if htere is an interfacce file
     for extension in &["cmj", "cmi", "cmt", "cmti"] {
-        remove_compile_asset(
-            source_file,
-            package_name,
-            namespace,
-            root_path,
-            is_root,
-            extension,
-        );
+        remove_compile_asset(source_file, namespace, build_dir, extension);
     }
 }
 
-pub fn clean_mjs_files(build_state: &BuildState, project_root: &str) {
+pub fn clean_mjs_files(build_state: &BuildState) {
     // get all rescript file locations
     let rescript_file_locations = build_state
         .modules
@@ -94,24 +76,20 @@ pub fn clean_mjs_files(build_state: &BuildState, project_root: &str) {
         .filter_map(|module| match &module.source_type {
             SourceType::SourceFile(source_file) => {
                 let package = build_state.packages.get(&module.package_name).unwrap();
+                // the JavaScript of packages that are built out of tree is removed with the build dir
+                if package.is_built_out_of_tree() {
+                    return None;
+                }
                 let root_package = build_state
                     .packages
                     .get(&build_state.root_config_name)
                     .expect("Could not find root package");
                 Some((
-                    std::path::PathBuf::from(helpers::get_package_path(
-                        &project_root,
-                        &module.package_name,
-                        package.is_root,
-                    ))
-                    .join(source_file.implementation.path.to_string())
-                    .to_string_lossy()
-                    .to_string(),
-                    root_package
-                        .bsconfig
-                        .suffix
-                        .to_owned()
-                        .unwrap_or(bsconfig::Suffix::Mjs),
+                    std::path::PathBuf::from(&package.package_dir)
+                        .join(source_file.implementation.path.to_string())
+                        .to_string_lossy()
+                        .to_string(),
+                    root_package.get_suffix(),
                 ))
             }
             _ => None,
@@ -148,39 +126,23 @@ pub fn cleanup_previous_build(
         .map(|res_file_location| {
             let AstModule {
                 module_name,
-                package_name,
                 namespace: package_namespace,
                 ast_file_path,
-                is_root,
+                package_dir,
+                build_dir,
                 suffix,
                 ..
             } = compile_assets_state
                 .ast_modules
                 .get(&res_file_location.to_string())
                 .expect("Could not find module name for ast file");
-            remove_compile_assets(
-                res_file_location,
-                package_name,
-                package_namespace,
-                &build_state.project_root,
-                *is_root,
-            );
+            remove_compile_assets(res_file_location, package_namespace, build_dir);
             remove_mjs_file(
-                &res_file_location,
-                &suffix.to_owned().unwrap_or(bsconfig::Suffix::Mjs),
-            );
-            remove_iast(
-                res_file_location,
-                package_name,
-                &build_state.project_root,
-                *is_root,
-            );
-            remove_ast(
-                res_file_location,
-                package_name,
-                &build_state.project_root,
-                *is_root,
+                &get_mjs_file_location(res_file_location, package_dir, build_dir),
+                suffix,
             );
+            remove_iast(res_file_location, build_dir);
+            remove_ast(res_file_location, build_dir);
             match helpers::get_extension(ast_file_path).as_str() {
                 "iast" => Some(module_name.to_owned()),
                 "ast" => None,
@@ -192,6 +154,11 @@ pub fn cleanup_previous_build(
         .filter_map(|module_name| module_name.to_owned())
         .collect::<AHashSet<String>>();
 
+    let suffix = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Could not find root package")
+        .get_suffix();
+
     compile_assets_state
         .ast_rescript_file_locations
         .intersection(&compile_assets_state.rescript_file_locations)
@@ -201,6 +168,9 @@ pub fn cleanup_previous_build(
                 module_name,
                 last_modified: ast_last_modified,
                 ast_file_path,
+                package_dir,
+                build_dir,
+                suffix: previous_suffix,
                 ..
             } = compile_assets_state
                 .ast_modules
@@ -211,17 +181,28 @@ pub fn cleanup_previous_build(
                 .get_mut(module_name)
                 .expect("Could not find module for ast file");
 
+            // the parser writes the AST again when only whitespace or comments changed, so the compile
+            // assets are also up to date when the AST is newer, but the same as the one they were
+            // compiled from
             let compile_dirty = compile_assets_state.cmi_modules.get(module_name);
             if let Some(compile_dirty) = compile_dirty {
-                let last_modified = Some(ast_last_modified);
-
-                if let Some(last_modified) = last_modified {
-                    if compile_dirty > &last_modified && !deleted_interfaces.contains(module_name) {
-                        module.compile_dirty = false;
-                    }
+                if (compile_dirty > ast_last_modified || parse::is_ast_compiled(ast_file_path))
+                    && !deleted_interfaces.contains(module_name)
+                {
+                    module.compile_dirty = false;
                 }
             }
 
+            // the suffix changed since the last build, so we remove the JavaScript file with the old
+            // suffix, and compile the module again to generate the new one
+            if previous_suffix != &suffix && !helpers::is_interface_ast_file(ast_file_path) {
+                remove_mjs_file(
+                    &get_mjs_file_location(res_file_location, package_dir, build_dir),
+                    previous_suffix,
+                );
+                module.compile_dirty = true;
+            }
+
             match &mut module.source_type {
                 SourceType::MlMap(_) => unreachable!("MlMap is not matched with a ReScript file"),
                 SourceType::SourceFile(source_file) => {
@@ -266,6 +247,15 @@ pub fn cleanup_previous_build(
             });
         });
 
+    compile_assets_state
+        .cmj_modules
+        .iter()
+        .for_each(|(module_name, last_modified)| {
+            if let Some(module) = build_state.modules.get_mut(module_name) {
+                module.last_compiled_cmj = Some(*last_modified);
+            }
+        });
+
     let ast_module_names = compile_assets_state
         .ast_modules
         .values()
@@ -308,6 +298,21 @@ pub fn cleanup_previous_build(
     )
 }
 
+/// Keeps the suffix the JavaScript files were generated with, so we can clean them up in the next
+/// build when the suffix changes. This should only be called when all modules were compiled, so
+/// the files of the modules that were not compiled yet are still cleaned up next time.
+pub fn write_suffix_markers(build_state: &BuildState) {
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Could not find root package");
+    build_state.get_built_packages().into_iter().for_each(|package| {
+        let _ = std::fs::write(
+            helpers::get_suffix_marker_path(&package.build_dir),
+            root_package.get_suffix().to_string(),
+        );
+    });
+}
+
 fn failed_to_parse(module: &Module) -> bool {
     match &module.source_type {
         SourceType::SourceFile(SourceFile {
@@ -358,18 +363,8 @@ pub fn cleanup_after_build(build_state: &BuildState) {
         if failed_to_parse(module) {
             match &module.source_type {
                 SourceType::SourceFile(source_file) => {
-                    remove_iast(
-                        &source_file.implementation.path,
-                        &module.package_name,
-                        &build_state.project_root,
-                        package.is_root,
-                    );
-                    remove_ast(
-                        &source_file.implementation.path,
-                        &module.package_name,
-                        &build_state.project_root,
-                        package.is_root,
-                    );
+                    remove_iast(&source_file.implementation.path, &package.build_dir);
+                    remove_ast(&source_file.implementation.path, &package.build_dir);
                 }
                 _ => (),
             }
@@ -385,10 +380,8 @@ pub fn cleanup_after_build(build_state: &BuildState) {
                     // unecessary mark all the dependents as dirty, when there is no change in the interface
                     remove_compile_asset(
                         &source_file.implementation.path,
-                        &module.package_name,
                         &package.namespace,
-                        &build_state.project_root,
-                        package.is_root,
+                        &package.build_dir,
                         "cmt",
                     );
                 }
@@ -398,10 +391,10 @@ pub fn cleanup_after_build(build_state: &BuildState) {
     });
 }
 
-pub fn clean(path: &str) {
+pub fn clean(path: &str) -> Result<(), String> {
     let project_root = helpers::get_abs_path(path);
-    let packages = packages::make(&None, &project_root);
-    let root_config_name = packages::get_package_name(&project_root);
+    let packages = packages::make(&None, &project_root)?;
+    let root_config_name = packages::get_package_name(&project_root)?;
 
     let timing_clean_compiler_assets = Instant::now();
     print!(
@@ -420,13 +413,17 @@ pub fn clean(path: &str) {
         );
         std::io::stdout().flush().unwrap();
 
-        let path_str = helpers::get_build_path(&project_root, &package.name, package.is_root);
+        let path_str = helpers::get_build_path(&package.build_dir);
         let path = std::path::Path::new(&path_str);
         let _ = std::fs::remove_dir_all(path);
 
-        let path_str = helpers::get_bs_build_path(&project_root, &package.name, package.is_root);
+        let path_str = helpers::get_bs_build_path(&package.build_dir);
         let path = std::path::Path::new(&path_str);
         let _ = std::fs::remove_dir_all(path);
+
+        if package.is_built_out_of_tree() {
+            let _ = std::fs::remove_dir_all(&package.build_dir);
+        }
     });
     let timing_clean_compiler_assets_elapsed = timing_clean_compiler_assets.elapsed();
 
@@ -442,9 +439,15 @@ pub fn clean(path: &str) {
     let timing_clean_mjs = Instant::now();
     print!("{} {} Cleaning mjs files...", style("[2/2]").bold().dim(), SWEEP);
     std::io::stdout().flush().unwrap();
-    let mut build_state = BuildState::new(project_root.to_owned(), root_config_name, packages);
+    // cleaning doesn't run the compiler
+    let mut build_state = BuildState::new(
+        project_root.to_owned(),
+        root_config_name,
+        packages,
+        Compiler::default(),
+    );
     packages::parse_packages(&mut build_state);
-    clean_mjs_files(&build_state, &project_root);
+    clean_mjs_files(&build_state);
     let timing_clean_mjs_elapsed = timing_clean_mjs.elapsed();
     println!(
         "{}\r{} {}Cleaned mjs files in {:.2}s",
@@ -454,4 +457,134 @@ pub fn clean(path: &str) {
         timing_clean_mjs_elapsed.as_secs_f64()
     );
     std::io::stdout().flush().unwrap();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cleanup_previous_build, get_mjs_file_location};
+    use crate::build::build_types::*;
+    use crate::build::compiler::Compiler;
+    use crate::build::packages::{Namespace, Package};
+    use ahash::{AHashMap, AHashSet};
+    use std::fs;
+    use std::path::Path;
+    use std::time::SystemTime;
+
+    fn create_package(name: &str, suffix: &str, package_dir: &Path, build_dir: &Path) -> Package {
+        Package {
+            name: name.to_string(),
+            bsconfig: serde_json::from_str(&format!(
+                r#"{{"name": "{}", "sources": "src", "suffix": "{}"}}"#,
+                name, suffix
+            ))
+            .unwrap(),
+            source_folders: AHashSet::new(),
+            source_files: None,
+            namespace: Namespace::NoNamespace,
+            modules: None,
+            package_dir: package_dir.to_string_lossy().to_string(),
+            build_dir: build_dir.to_string_lossy().to_string(),
+            dirs: None,
+            is_pinned_dep: false,
+            is_root: name == "root",
+        }
+    }
+
+    #[test]
+    fn test_mjs_file_location_of_a_package_built_out_of_tree() {
+        assert_eq!(
+            get_mjs_file_location(
+                "/project/node_modules/dep/src/A.res",
+                "/project/node_modules/dep",
+                "/project/lib/rewatch/dep"
+            ),
+            "/project/lib/rewatch/dep/src/A.res"
+        );
+        assert_eq!(
+            get_mjs_file_location("/project/src/A.res", "/project", "/project"),
+            "/project/src/A.res"
+        );
+    }
+
+    #[test]
+    fn test_suffix_change_removes_the_javascript_from_the_build_dir() {
+        let project = tempfile::tempdir().unwrap();
+        let package_dir = project.path().join("node_modules").join("dep");
+        let build_dir = project.path().join("lib").join("rewatch").join("dep");
+        fs::create_dir_all(package_dir.join("src")).unwrap();
+        fs::create_dir_all(build_dir.join("src")).unwrap();
+        // the JavaScript that is published with the package should stay
+        fs::write(package_dir.join("src").join("Dep.mjs"), "").unwrap();
+        fs::write(build_dir.join("src").join("Dep.mjs"), "").unwrap();
+
+        let packages = AHashMap::from([
+            (
+                String::from("root"),
+                create_package("root", ".bs.js", project.path(), project.path()),
+            ),
+            (
+                String::from("dep"),
+                create_package("dep", ".bs.js", &package_dir, &build_dir),
+            ),
+        ]);
+        let mut build_state = BuildState::new(
+            project.path().to_string_lossy().to_string(),
+            String::from("root"),
+            packages,
+            Compiler::default(),
+        );
+        build_state.insert_module(
+            "Dep",
+            Module {
+                source_type: SourceType::SourceFile(SourceFile {
+                    implementation: Implementation {
+                        path: String::from("src/Dep.res"),
+                        parse_state: ParseState::Success,
+                        compile_state: CompileState::Pending,
+                        last_modified: SystemTime::UNIX_EPOCH,
+                        dirty: true,
+                    },
+                    interface: None,
+                }),
+                deps: AHashSet::new(),
+                dependents: AHashSet::new(),
+                package_name: String::from("dep"),
+                compile_dirty: true,
+                last_compiled_cmi: None,
+                last_compiled_cmt: None,
+                last_compiled_cmj: None,
+            },
+        );
+
+        let res_file_location = package_dir
+            .join("src")
+            .join("Dep.res")
+            .to_string_lossy()
+            .to_string();
+        let ast_module = AstModule {
+            module_name: String::from("Dep"),
+            package_name: String::from("dep"),
+            namespace: Namespace::NoNamespace,
+            last_modified: SystemTime::now(),
+            ast_file_path: build_dir.join("lib/bs/src/Dep.ast").to_string_lossy().to_string(),
+            package_dir: package_dir.to_string_lossy().to_string(),
+            build_dir: build_dir.to_string_lossy().to_string(),
+            suffix: Default::default(),
+        };
+        let compile_assets_state = CompileAssetsState {
+            ast_modules: AHashMap::from([(res_file_location.clone(), ast_module)]),
+            cmi_modules: AHashMap::from([(String::from("Dep"), SystemTime::now())]),
+            cmt_modules: AHashMap::new(),
+            cmj_modules: AHashMap::new(),
+            ast_rescript_file_locations: AHashSet::from([res_file_location.clone()]),
+            rescript_file_locations: AHashSet::from([res_file_location]),
+        };
+
+        cleanup_previous_build(&mut build_state, compile_assets_state);
+
+        assert!(!build_dir.join("src").join("Dep.mjs").exists());
+        assert!(package_dir.join("src").join("Dep.mjs").exists());
+        assert!(build_state.modules.get("Dep").unwrap().compile_dirty);
+    }
 }
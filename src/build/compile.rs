@@ -1,27 +1,410 @@
-mod dependency_cycle;
+pub mod dependency_cycle;
 
 use super::build_types::*;
 use super::logs;
 use super::packages;
+use super::parse;
+use super::version::Version;
 use crate::bsconfig;
 use crate::helpers;
-use ahash::AHashSet;
+use ahash::{AHashMap, AHashSet};
 use console::style;
 use log::debug;
 use log::{info, log_enabled, Level::Info};
-use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::path::Path;
-use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// A module is compiled in one or two steps. When a module has an interface file, the interface
+/// is compiled separately from the implementation. Modules that depend on it only need the `.cmi`
+/// of the interface, so they can start compiling while the implementation is still compiling.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum CompileUnit {
+    Interface(String),
+    Implementation(String),
+}
+
+impl CompileUnit {
+    fn module_name(&self) -> &str {
+        match self {
+            CompileUnit::Interface(module_name) | CompileUnit::Implementation(module_name) => module_name,
+        }
+    }
+}
+
+fn get_units(module_name: &str, module: &Module) -> Vec<CompileUnit> {
+    match module.get_interface() {
+        Some(_) => vec![
+            CompileUnit::Interface(module_name.to_string()),
+            CompileUnit::Implementation(module_name.to_string()),
+        ],
+        None => vec![CompileUnit::Implementation(module_name.to_string())],
+    }
+}
+
+/// The units that wait on the given unit: the implementation waits on the interface of the same
+/// module, and all units of the dependents wait on the unit producing the `.cmi`. The
+/// implementations of the dependents also wait on the implementation, because the compiler inlines
+/// values from the `.cmj` of the modules they depend on.
+fn get_unit_dependents(
+    build_state: &BuildState,
+    compile_universe: &AHashSet<String>,
+    unit: &CompileUnit,
+) -> Vec<CompileUnit> {
+    let module = build_state.get_module(unit.module_name()).unwrap();
+    let dependents = module
+        .dependents
+        .iter()
+        .filter(|dependent| compile_universe.contains(*dependent));
+    match unit {
+        CompileUnit::Interface(module_name) => dependents
+            .flat_map(|dependent| get_units(dependent, build_state.get_module(dependent).unwrap()))
+            .chain([CompileUnit::Implementation(module_name.to_string())])
+            .collect(),
+        CompileUnit::Implementation(_) if module.get_interface().is_some() => dependents
+            .map(|dependent| CompileUnit::Implementation(dependent.to_string()))
+            .collect(),
+        CompileUnit::Implementation(_) => dependents
+            .flat_map(|dependent| get_units(dependent, build_state.get_module(dependent).unwrap()))
+            .collect(),
+    }
+}
+
+/// The number of units in the compile universe the given unit waits on.
+fn get_unit_dependencies_count(
+    build_state: &BuildState,
+    compile_universe: &AHashSet<String>,
+    unit: &CompileUnit,
+) -> usize {
+    let module = build_state.get_module(unit.module_name()).unwrap();
+    let deps = module.deps.intersection(compile_universe);
+    match unit {
+        CompileUnit::Interface(_) => deps.count(),
+        // the unit producing the `.cmi` of every dependency, and the implementation of the
+        // dependencies with an interface, for the `.cmj`
+        CompileUnit::Implementation(_) => {
+            let deps_count = deps
+                .map(|dep| match build_state.get_module(dep).unwrap().get_interface() {
+                    Some(_) => 2,
+                    None => 1,
+                })
+                .sum::<usize>();
+            match module.get_interface() {
+                Some(_) => deps_count + 1,
+                None => deps_count,
+            }
+        }
+    }
+}
+
+/// The result of compiling a single unit. `is_clean` means that neither the `.cmi` nor the `.cmj`
+/// of the module changed, so the modules that depend on it don't have to be recompiled.
+struct CompileResult {
+    unit: CompileUnit,
+    result: Result<Option<String>, String>,
+    is_clean: bool,
+    is_compiled: bool,
+}
+
+struct SchedulerState {
+    // units of which all dependencies are compiled, ordered by the length of the longest
+    // chain of dependents (critical path first)
+    ready: BinaryHeap<(usize, Reverse<CompileUnit>)>,
+    // the number of units in the compile universe that still need to be compiled first
+    pending_deps: AHashMap<CompileUnit, usize>,
+    dirty_modules: AHashSet<String>,
+    // modules that failed to parse or compile
+    failed_modules: AHashSet<String>,
+    // modules that are skipped, because one of their dependencies failed (the value)
+    blocked_modules: AHashMap<String, String>,
+    results: Vec<CompileResult>,
+    has_errors: bool,
+}
+
+struct Scheduler<'a, F: Fn() + Sync> {
+    build_state: &'a BuildState,
+    compile_universe: &'a AHashSet<String>,
+    rescript_version: &'a Version,
+    keep_going: bool,
+    cancelled: &'a AtomicBool,
+    inc: &'a F,
+    critical_path_lengths: AHashMap<CompileUnit, usize>,
+    state: Mutex<SchedulerState>,
+}
+
+/// Computes for every unit in the compile universe the length of the longest chain of
+/// dependents that are waiting on it. Units with the longest chain are scheduled first, because
+/// they determine how long the compile phase takes at least.
+fn get_critical_path_lengths(
+    build_state: &BuildState,
+    compile_universe: &AHashSet<String>,
+    units: &[CompileUnit],
+) -> AHashMap<CompileUnit, usize> {
+    let mut lengths: AHashMap<CompileUnit, usize> = AHashMap::new();
+    let mut visiting: AHashSet<CompileUnit> = AHashSet::new();
+
+    for unit in units {
+        if lengths.contains_key(unit) {
+            continue;
+        }
+
+        // every frame holds the unit, its dependents and the position of the next dependent to
+        // visit. A unit is only finished after all of its dependents, so the lengths are computed in
+        // reverse topological order without recursing (long chains would overflow the stack).
+        visiting.insert(unit.to_owned());
+        let mut call_stack = vec![(
+            unit.to_owned(),
+            get_unit_dependents(build_state, compile_universe, unit),
+            0,
+        )];
+
+        while let Some((unit, dependents, next)) = call_stack.pop() {
+            if let Some(dependent) = dependents.get(next) {
+                let dependent = dependent.to_owned();
+                call_stack.push((unit, dependents, next + 1));
+                // guard against cycles, they are reported separately
+                if !lengths.contains_key(&dependent) && visiting.insert(dependent.to_owned()) {
+                    let dependents = get_unit_dependents(build_state, compile_universe, &dependent);
+                    call_stack.push((dependent, dependents, 0));
+                }
+                continue;
+            }
+
+            let length = 1 + dependents
+                .iter()
+                .filter_map(|dependent| lengths.get(dependent))
+                .max()
+                .unwrap_or(&0);
+            visiting.remove(&unit);
+            lengths.insert(unit, length);
+        }
+    }
+
+    lengths
+}
+
+/// Returns the module that blocks the given unit from compiling. That is the module itself when
+/// it failed to parse, or when its interface failed to compile, or any of its dependencies that
+/// failed (or was blocked itself).
+fn get_blocking_module<F: Fn() + Sync>(
+    scheduler: &Scheduler<'_, F>,
+    state: &SchedulerState,
+    unit: &CompileUnit,
+) -> Option<String> {
+    let module = scheduler.build_state.get_module(unit.module_name()).unwrap();
+    if module.has_parse_error() || state.failed_modules.contains(unit.module_name()) {
+        return Some(unit.module_name().to_string());
+    }
+    let mut deps = module
+        .deps
+        .intersection(scheduler.compile_universe)
+        .collect::<Vec<&String>>();
+    deps.sort();
+    deps.iter().find_map(|dep| {
+        if state.failed_modules.contains(*dep) {
+            Some(dep.to_string())
+        } else {
+            state
+                .blocked_modules
+                .get(*dep)
+                .map(|blocking| blocking.to_string())
+        }
+    })
+}
+
+/// Takes the most critical unit from the ready queue and compiles it. When it's done, the
+/// dependents of which all dependencies are compiled become ready, and a new task is spawned for
+/// each of them. This way a unit starts compiling the moment its last dependency finishes,
+/// instead of waiting for a whole wave of modules.
+fn run_next<'a, F: Fn() + Sync>(scope: &rayon::Scope<'a>, scheduler: &'a Scheduler<'a, F>) {
+    let (unit, is_dirty, is_blocked) = {
+        let mut state = scheduler.state.lock().unwrap();
+        // when the build is cancelled, we don't start compiling new units. The units that are
+        // already compiling finish, so their compile assets stay consistent
+        if (state.has_errors && !scheduler.keep_going) || scheduler.cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+        match state.ready.pop() {
+            Some((_, Reverse(unit))) => {
+                let is_dirty = state.dirty_modules.contains(unit.module_name());
+                let is_blocked = match get_blocking_module(scheduler, &state, &unit) {
+                    Some(blocking_module) => {
+                        state
+                            .blocked_modules
+                            .insert(unit.module_name().to_string(), blocking_module);
+                        true
+                    }
+                    None => false,
+                };
+                (unit, is_dirty, is_blocked)
+            }
+            None => return,
+        }
+    };
+
+    let result = if is_blocked {
+        CompileResult {
+            unit: unit.to_owned(),
+            result: Ok(None),
+            is_clean: false,
+            is_compiled: false,
+        }
+    } else {
+        compile_unit(scheduler.build_state, &unit, is_dirty, scheduler.rescript_version)
+    };
+    if !(log_enabled!(Info)) {
+        (scheduler.inc)();
+    }
+
+    let module = scheduler.build_state.get_module(unit.module_name()).unwrap();
+    let mut state = scheduler.state.lock().unwrap();
+    info!(
+        "Compiled: {} out of {} units",
+        state.results.len() + 1,
+        scheduler.critical_path_lengths.len(),
+    );
+    if result.result.is_err() {
+        state.has_errors = true;
+        state.failed_modules.insert(unit.module_name().to_string());
+    }
+    // mark the reverse deps as dirty when the cmi or cmj is not clean
+    if !result.is_clean {
+        for dependent in module.dependents.iter() {
+            state.dirty_modules.insert(dependent.to_string());
+        }
+    }
+    for dependent in get_unit_dependents(scheduler.build_state, scheduler.compile_universe, &unit) {
+        let pending = state.pending_deps.get_mut(&dependent).unwrap();
+        *pending -= 1;
+        if *pending == 0 {
+            let priority = scheduler
+                .critical_path_lengths
+                .get(&dependent)
+                .copied()
+                .unwrap_or(0);
+            state.ready.push((priority, Reverse(dependent)));
+            scope.spawn(move |scope| run_next(scope, scheduler));
+        }
+    }
+    state.results.push(result);
+}
+
+fn compile_unit(
+    build_state: &BuildState,
+    unit: &CompileUnit,
+    is_dirty: bool,
+    rescript_version: &Version,
+) -> CompileResult {
+    let module = build_state.get_module(unit.module_name()).unwrap();
+    let package = build_state
+        .get_package(&module.package_name)
+        .expect("Package not found");
+
+    if !is_dirty {
+        // we are sure we don't have to compile this, so we can mark it as compiled and clean
+        return CompileResult {
+            unit: unit.to_owned(),
+            result: Ok(None),
+            is_clean: true,
+            is_compiled: false,
+        };
+    }
+
+    match module.source_type.to_owned() {
+        SourceType::MlMap(_) => {
+            // the mlmap needs to be compiled before the files are compiled
+            // in the same namespace, otherwise we get a compile error
+            // this is why mlmap is compiled in the AST generation stage
+            // compile_mlmap(&module.package, module_name, &project_root);
+            CompileResult {
+                unit: unit.to_owned(),
+                result: Ok(None),
+                is_clean: false,
+                is_compiled: false,
+            }
+        }
+        SourceType::SourceFile(source_file) => {
+            let cmi_path = helpers::get_compiler_asset(
+                &source_file.implementation.path,
+                &package.namespace,
+                &package.build_dir,
+                "cmi",
+            );
+
+            let cmj_path = helpers::get_compiler_asset(
+                &source_file.implementation.path,
+                &package.namespace,
+                &package.build_dir,
+                "cmj",
+            );
+
+            let cmi_digest = helpers::compute_file_hash(&cmi_path);
+            let cmj_digest = helpers::compute_file_hash(&cmj_path);
+
+            let root_package = build_state.get_package(&build_state.root_config_name).unwrap();
+
+            let result = match (unit, source_file.interface) {
+                (CompileUnit::Interface(_), Some(Interface { path, .. })) => compile_file(
+                    &package,
+                    &root_package,
+                    build_state,
+                    &helpers::get_iast_path(&path, &package.build_dir),
+                    module,
+                    &rescript_version,
+                    true,
+                ),
+                _ => compile_file(
+                    &package,
+                    &root_package,
+                    build_state,
+                    &helpers::get_ast_path(&source_file.implementation.path, &package.build_dir),
+                    module,
+                    &rescript_version,
+                    false,
+                ),
+            };
+            let cmi_digest_after = helpers::compute_file_hash(&cmi_path);
+            let cmj_digest_after = helpers::compute_file_hash(&cmj_path);
+
+            // the dependents only need to be recompiled when the cmi changed. When the module has an
+            // interface, the cmi is produced by the interface, the implementation only reads it
+            let is_clean_cmi = match (cmi_digest, cmi_digest_after) {
+                (Some(cmi_digest), Some(cmi_digest_after)) => cmi_digest.eq(&cmi_digest_after),
+
+                _ => false,
+            };
+            // the compiler inlines constants and small functions from the cmj of the modules a
+            // module depends on, so the dependents also need to be recompiled when the cmj changed
+            let is_clean_cmj = match unit {
+                CompileUnit::Interface(_) => true,
+                CompileUnit::Implementation(_) => match (cmj_digest, cmj_digest_after) {
+                    (Some(cmj_digest), Some(cmj_digest_after)) => cmj_digest.eq(&cmj_digest_after),
+                    _ => false,
+                },
+            };
+
+            CompileResult {
+                unit: unit.to_owned(),
+                result,
+                is_clean: is_clean_cmi && is_clean_cmj,
+                is_compiled: true,
+            }
+        }
+    }
+}
 
 pub fn compile(
     mut build_state: &mut BuildState,
     deleted_module_names: &AHashSet<String>,
-    rescript_version: &str,
+    rescript_version: &Version,
+    keep_going: bool,
+    cancelled: &AtomicBool,
     inc: impl Fn() -> () + std::marker::Sync,
     set_length: impl Fn(u64) -> (),
 ) -> (String, String, usize) {
-    let mut compiled_modules = AHashSet::<String>::new();
-
     mark_modules_with_deleted_deps_dirty(&mut build_state, &deleted_module_names);
     mark_modules_with_expired_deps_dirty(&mut build_state);
 
@@ -37,30 +420,9 @@ pub fn compile(
         })
         .collect::<AHashSet<String>>();
 
-    // dirty_modules.iter().for_each(|m| println!("dirty module: {}", m));
-    // println!("{} dirty modules", dirty_modules.len());
-    // let mut sorted_dirty_modules = dirty_modules.iter().collect::<Vec<&String>>();
-    // sorted_dirty_modules.sort();
-    // // dirty_modules.iter().for_each(|m| println!("dirty module: {}", m));
-    // sorted_dirty_modules
-    //     .iter()
-    //     .for_each(|m| println!("dirty module: {}", m));
-
-    // for sure clean modules -- after checking the hash of the cmi
-    let mut clean_modules = AHashSet::<String>::new();
-
-    // TODO: calculate the real dirty modules from the orginal dirty modules in each iteration
-    // taken into account the modules that we know are clean, so they don't propagate through the
-    // deps graph
-    // create a hashset of all clean modules form the file-hashes
-    let mut loop_count = 0;
-    let mut files_total_count = compiled_modules.len();
-    let mut files_current_loop_count;
     let mut compile_errors = "".to_string();
     let mut compile_warnings = "".to_string();
     let mut num_compiled_modules = 0;
-    let mut sorted_modules = build_state.module_names.iter().collect::<Vec<&String>>();
-    sorted_modules.sort();
 
     // this is the whole "compile universe" all modules that might be dirty
     // we get this by traversing from the dirty modules to all the modules that
@@ -83,299 +445,160 @@ pub fn compile(
         }
     }
 
-    let compile_universe_count = compile_universe.len();
-    set_length(compile_universe_count as u64);
+    let units = compile_universe
+        .iter()
+        .flat_map(|module_name| get_units(module_name, build_state.get_module(module_name).unwrap()))
+        .collect::<Vec<CompileUnit>>();
+    set_length(units.len() as u64);
 
-    // start off with all modules that have no deps in this compile universe
-    let mut in_progress_modules = compile_universe
+    let critical_path_lengths = get_critical_path_lengths(build_state, &compile_universe, &units);
+    let pending_deps = units
         .iter()
-        .filter(|module_name| {
-            let module = build_state.get_module(*module_name).unwrap();
-            module.deps.intersection(&compile_universe).count() == 0
+        .map(|unit| {
+            (
+                unit.to_owned(),
+                get_unit_dependencies_count(build_state, &compile_universe, unit),
+            )
         })
-        .map(|module_name| module_name.to_string())
-        .collect::<AHashSet<String>>();
-
-    loop {
-        files_current_loop_count = 0;
-        loop_count += 1;
-
-        info!(
-            "Compiled: {} out of {}. Compile loop: {}",
-            files_total_count,
-            compile_universe.len(),
-            loop_count,
-        );
+        .collect::<AHashMap<CompileUnit, usize>>();
 
-        let current_in_progres_modules = in_progress_modules.clone();
-
-        current_in_progres_modules
-            .par_iter()
-            .map(|module_name| {
-                let module = build_state.get_module(module_name).unwrap();
-                let package = build_state
-                    .get_package(&module.package_name)
-                    .expect("Package not found");
-                // all dependencies that we care about are compiled
-                if module
-                    .deps
-                    .intersection(&compile_universe)
-                    .all(|dep| compiled_modules.contains(dep))
-                {
-                    if !module.compile_dirty {
-                        // we are sure we don't have to compile this, so we can mark it as compiled and clean
-                        return Some((module_name.to_string(), Ok(None), Some(Ok(None)), true, false));
-                    }
-                    match module.source_type.to_owned() {
-                        SourceType::MlMap(_) => {
-                            // the mlmap needs to be compiled before the files are compiled
-                            // in the same namespace, otherwise we get a compile error
-                            // this is why mlmap is compiled in the AST generation stage
-                            // compile_mlmap(&module.package, module_name, &project_root);
-                            Some((
-                                package.namespace.to_suffix().unwrap(),
-                                Ok(None),
-                                Some(Ok(None)),
-                                false,
-                                false,
-                            ))
-                        }
-                        SourceType::SourceFile(source_file) => {
-                            let cmi_path = helpers::get_compiler_asset(
-                                &source_file.implementation.path,
-                                &module.package_name,
-                                &package.namespace,
-                                &build_state.project_root,
-                                "cmi",
-                                package.is_root,
-                            );
-
-                            let cmi_digest = helpers::compute_file_hash(&cmi_path);
-
-                            let package = build_state
-                                .get_package(&module.package_name)
-                                .expect("Package not found");
-
-                            let root_package =
-                                build_state.get_package(&build_state.root_config_name).unwrap();
-
-                            let interface_result = match source_file.interface.to_owned() {
-                                Some(Interface { path, .. }) => {
-                                    let result = compile_file(
-                                        &package,
-                                        &root_package,
-                                        &helpers::get_iast_path(
-                                            &path,
-                                            &package.name,
-                                            &build_state.project_root,
-                                            package.is_root,
-                                        ),
-                                        module,
-                                        &build_state.project_root,
-                                        &rescript_version,
-                                        true,
-                                    );
-                                    Some(result)
-                                }
-                                _ => None,
-                            };
-                            let result = compile_file(
-                                &package,
-                                &root_package,
-                                &helpers::get_ast_path(
-                                    &source_file.implementation.path,
-                                    &package.name,
-                                    &build_state.project_root,
-                                    package.is_root,
-                                ),
-                                module,
-                                &build_state.project_root,
-                                &rescript_version,
-                                false,
-                            );
-                            // if let Err(error) = result.to_owned() {
-                            //     println!("{}", error);
-                            //     panic!("Implementation compilation error!");
-                            // }
-                            let cmi_digest_after = helpers::compute_file_hash(&cmi_path);
-
-                            // println!(
-                            //     "cmi path {}, digest: {:?} / {:?}",
-                            //     cmi_path, cmi_digest, cmi_digest_after
-                            // );
-
-                            // we want to compare both the hash of interface and the implementation
-                            // compile assets to verify that nothing changed. We also need to checke the interface
-                            // because we can include MyModule, so the modules that depend on this module might
-                            // change when this modules interface does not change, but the implementation does
-                            let is_clean_cmi = match (cmi_digest, cmi_digest_after) {
-                                (Some(cmi_digest), Some(cmi_digest_after)) => {
-                                    cmi_digest.eq(&cmi_digest_after)
-                                }
-
-                                _ => false,
-                            };
-
-                            Some((
-                                module_name.to_string(),
-                                result,
-                                interface_result,
-                                is_clean_cmi,
-                                true,
-                            ))
-                        }
-                    }
-                } else {
-                    None
-                }
-                .map(|res| {
-                    if !(log_enabled!(Info)) {
-                        inc();
-                    }
-                    res
-                })
-            })
-            .collect::<Vec<
-                Option<(
-                    String,
-                    Result<Option<String>, String>,
-                    Option<Result<Option<String>, String>>,
-                    bool,
-                    bool,
-                )>,
-            >>()
-            .iter()
-            .for_each(|result| match result {
-                Some((module_name, result, interface_result, is_clean, is_compiled)) => {
-                    in_progress_modules.remove(module_name);
-
-                    if *is_compiled {
-                        num_compiled_modules += 1;
-                    }
+    // start off with all units that have no deps in this compile universe
+    let ready = pending_deps
+        .iter()
+        .filter(|(_, pending)| **pending == 0)
+        .map(|(unit, _)| {
+            (
+                critical_path_lengths.get(unit).copied().unwrap_or(0),
+                Reverse(unit.to_owned()),
+            )
+        })
+        .collect::<BinaryHeap<(usize, Reverse<CompileUnit>)>>();
+    let num_ready = ready.len();
 
-                    files_current_loop_count += 1;
-                    compiled_modules.insert(module_name.to_string());
+    let scheduler = Scheduler {
+        build_state,
+        compile_universe: &compile_universe,
+        rescript_version,
+        keep_going,
+        cancelled,
+        inc: &inc,
+        critical_path_lengths,
+        state: Mutex::new(SchedulerState {
+            ready,
+            pending_deps,
+            dirty_modules,
+            failed_modules: AHashSet::new(),
+            blocked_modules: AHashMap::new(),
+            results: vec![],
+            has_errors: false,
+        }),
+    };
+    rayon::scope(|scope| {
+        for _ in 0..num_ready {
+            scope.spawn(|scope| run_next(scope, &scheduler));
+        }
+    });
+    let SchedulerState {
+        results,
+        dirty_modules,
+        blocked_modules,
+        ..
+    } = scheduler.state.into_inner().unwrap();
 
-                    if *is_clean {
-                        // actually add it to a list of clean modules
-                        clean_modules.insert(module_name.to_string());
-                    }
+    for module_name in dirty_modules.iter() {
+        if let Some(module) = build_state.modules.get_mut(module_name) {
+            module.compile_dirty = true;
+        }
+    }
 
-                    let module_dependents = build_state.get_module(module_name).unwrap().dependents.clone();
+    for CompileResult {
+        unit,
+        result,
+        is_compiled,
+        ..
+    } in results.iter()
+    {
+        if *is_compiled && matches!(unit, CompileUnit::Implementation(_)) {
+            num_compiled_modules += 1;
+        }
 
-                    // if not clean -- compile modules that depend on this module
-                    for dep in module_dependents.iter() {
-                        //  mark the reverse dep as dirty when the source is not clean
-                        if !*is_clean {
-                            let dep_module = build_state.modules.get_mut(dep).unwrap();
-                            //  mark the reverse dep as dirty when the source is not clean
-                            dep_module.compile_dirty = true;
-                        }
-                        if !compiled_modules.contains(dep) {
-                            in_progress_modules.insert(dep.to_string());
-                        }
+        let module = build_state.modules.get_mut(unit.module_name()).unwrap();
+        let package = build_state
+            .packages
+            .get(&module.package_name)
+            .expect("Package not found");
+        match module.source_type {
+            SourceType::MlMap(_) => (),
+            SourceType::SourceFile(ref mut source_file) => {
+                let compile_state = match unit {
+                    CompileUnit::Interface(_) => &mut source_file.interface.as_mut().unwrap().compile_state,
+                    CompileUnit::Implementation(_) => &mut source_file.implementation.compile_state,
+                };
+                match result {
+                    Ok(Some(err)) => {
+                        *compile_state = CompileState::Warning;
+                        logs::append(package, &err);
+                        compile_warnings.push_str(&err);
                     }
-
-                    let module = build_state.modules.get_mut(module_name).unwrap();
-                    let package = build_state
-                        .packages
-                        .get(&module.package_name)
-                        .expect("Package not found");
-                    match module.source_type {
-                        SourceType::MlMap(_) => (),
-                        SourceType::SourceFile(ref mut source_file) => {
-                            match result {
-                                Ok(Some(err)) => {
-                                    source_file.implementation.compile_state = CompileState::Warning;
-                                    logs::append(
-                                        &build_state.project_root,
-                                        package.is_root,
-                                        &package.name,
-                                        &err,
-                                    );
-                                    compile_warnings.push_str(&err);
-                                }
-                                Ok(None) => (),
-                                Err(err) => {
-                                    source_file.implementation.compile_state = CompileState::Error;
-                                    logs::append(
-                                        &build_state.project_root,
-                                        package.is_root,
-                                        &package.name,
-                                        &err,
-                                    );
-                                    compile_errors.push_str(&err);
-                                }
-                            };
-                            match interface_result {
-                                Some(Ok(Some(err))) => {
-                                    source_file.interface.as_mut().unwrap().compile_state =
-                                        CompileState::Warning;
-                                    logs::append(
-                                        &build_state.project_root,
-                                        package.is_root,
-                                        &package.name,
-                                        &err,
-                                    );
-                                    compile_warnings.push_str(&err);
-                                }
-                                Some(Ok(None)) => (),
-                                Some(Err(err)) => {
-                                    source_file.interface.as_mut().unwrap().compile_state =
-                                        CompileState::Error;
-                                    logs::append(
-                                        &build_state.project_root,
-                                        package.is_root,
-                                        &package.name,
-                                        &err,
-                                    );
-                                    compile_errors.push_str(&err);
-                                }
-                                _ => (),
-                            };
-                        }
+                    Ok(None) => (),
+                    Err(err) => {
+                        *compile_state = CompileState::Error;
+                        logs::append(package, &err);
+                        compile_errors.push_str(&err);
                     }
-                }
-                None => (),
-            });
-
-        files_total_count += files_current_loop_count;
-
-        if files_total_count == compile_universe_count {
-            break;
+                };
+            }
         }
-        if in_progress_modules.len() == 0 || in_progress_modules.eq(&current_in_progres_modules) {
-            // find the dependency cycle
-            let cycle = dependency_cycle::find(
-                &compile_universe
-                    .iter()
-                    .map(|s| (s, build_state.get_module(s).unwrap()))
-                    .collect::<Vec<(&String, &Module)>>(),
-            );
+    }
+
+    if !blocked_modules.is_empty() {
+        let mut blocked_modules = blocked_modules
+            .iter()
+            .filter(|(module_name, blocking_module)| module_name != blocking_module)
+            .map(|(module_name, blocking_module)| {
+                format!(
+                    "  {} (blocked by {})",
+                    helpers::format_namespaced_module_name(module_name),
+                    helpers::format_namespaced_module_name(blocking_module)
+                )
+            })
+            .collect::<Vec<String>>();
+        blocked_modules.sort();
+        if !blocked_modules.is_empty() {
             compile_errors.push_str(&format!(
                 "\n{}\n{}\n",
-                style("Can't continue... Found a circular dependency in your code:").red(),
-                dependency_cycle::format(&cycle)
-            ))
+                style(format!(
+                    "Skipped {} modules because their dependencies failed:",
+                    blocked_modules.len()
+                ))
+                .yellow(),
+                blocked_modules.join("\n")
+            ));
         }
-        if compile_errors.len() > 0 {
-            break;
-        };
+    }
+
+    if compile_errors.is_empty() && results.len() != units.len() && !cancelled.load(Ordering::Relaxed) {
+        // we could not schedule all modules, so there must be a dependency cycle
+        let cycles = dependency_cycle::find(
+            &compile_universe
+                .iter()
+                .map(|s| (s.to_string(), build_state.get_module(s).unwrap().to_owned()))
+                .collect::<AHashMap<String, Module>>(),
+        );
+        compile_errors.push_str(&dependency_cycle::format_error(&cycles, build_state));
     }
 
     (compile_errors, compile_warnings, num_compiled_modules)
 }
 
-fn compile_file(
+/// The arguments for the compiler that are the same for every file of a package. These are also
+/// part of the fingerprint of the package, so a change in these recompiles the package.
+pub fn compiler_args(
     package: &packages::Package,
     root_package: &packages::Package,
-    ast_path: &str,
-    module: &Module,
-    root_path: &str,
-    version: &str,
-    is_interface: bool,
-) -> Result<Option<String>, String> {
-    let build_path_abs = helpers::get_build_path(root_path, &package.name, package.is_root);
+    packages: &AHashMap<String, packages::Package>,
+    version: &Version,
+) -> Vec<String> {
     let bsc_flags = bsconfig::flatten_flags(&package.bsconfig.bsc_flags);
 
     let normal_deps = package
@@ -397,15 +620,65 @@ fn compile_file(
     let deps = vec![normal_deps]
         .concat()
         .into_iter()
-        .map(|x| {
-            vec![
-                "-I".to_string(),
-                helpers::canonicalize_string_path(&helpers::get_build_path(root_path, &x, package.is_root))
-                    .unwrap(),
-            ]
-        })
+        // the dependencies are resolved when the package tree is built, missing ones are reported
+        // there
+        .filter_map(|x| packages.get(&x))
+        .map(|dep| vec!["-I".to_string(), helpers::get_build_path(&dep.build_dir)])
         .collect::<Vec<Vec<String>>>();
 
+    let jsx_args = package.get_jsx_args(root_package);
+    let jsx_module_args = package.get_jsx_module_args(root_package);
+    let jsx_mode_args = package.get_jsx_mode_args(root_package);
+    let jsx_preserve_args = package.get_jsx_preserve_args(root_package);
+    let uncurried_args = package.get_uncurried_args(version, root_package);
+
+    let warning_args: Vec<String> = match package.bsconfig.warnings.to_owned() {
+        None => vec![],
+        Some(warnings) => {
+            let warn_number = match warnings.number {
+                None => vec![],
+                Some(warnings) => {
+                    vec!["-w".to_string(), warnings.to_string()]
+                }
+            };
+
+            let warn_error = match warnings.error {
+                Some(bsconfig::Error::Catchall(true)) => {
+                    vec!["-warn-error".to_string(), "A".to_string()]
+                }
+                Some(bsconfig::Error::Qualified(errors)) => {
+                    vec!["-warn-error".to_string(), errors.to_string()]
+                }
+                _ => vec![],
+            };
+
+            vec![warn_number, warn_error].concat()
+        }
+    };
+
+    [
+        deps.concat(),
+        jsx_args,
+        jsx_module_args,
+        jsx_mode_args,
+        jsx_preserve_args,
+        uncurried_args,
+        bsc_flags,
+        warning_args,
+    ]
+    .concat()
+}
+
+fn compile_file(
+    package: &packages::Package,
+    root_package: &packages::Package,
+    build_state: &BuildState,
+    ast_path: &str,
+    module: &Module,
+    version: &Version,
+    is_interface: bool,
+) -> Result<Option<String>, String> {
+    let build_path_abs = helpers::get_build_path(&package.build_dir);
     let implementation_file_path = match module.source_type {
         SourceType::SourceFile(ref source_file) => &source_file.implementation.path,
         _ => panic!("Not a source file"),
@@ -434,35 +707,6 @@ fn compile_file(
         packages::Namespace::NoNamespace => vec![],
     };
 
-    let jsx_args = root_package.get_jsx_args();
-    let jsx_module_args = root_package.get_jsx_module_args();
-    let jsx_mode_args = root_package.get_jsx_mode_args();
-    let uncurried_args = package.get_uncurried_args(version, &root_package);
-
-    let warning_args: Vec<String> = match package.bsconfig.warnings.to_owned() {
-        None => vec![],
-        Some(warnings) => {
-            let warn_number = match warnings.number {
-                None => vec![],
-                Some(warnings) => {
-                    vec!["-w".to_string(), warnings.to_string()]
-                }
-            };
-
-            let warn_error = match warnings.error {
-                Some(bsconfig::Error::Catchall(true)) => {
-                    vec!["-warn-error".to_string(), "A".to_string()]
-                }
-                Some(bsconfig::Error::Qualified(errors)) => {
-                    vec!["-warn-error".to_string(), errors.to_string()]
-                }
-                _ => vec![],
-            };
-
-            vec![warn_number, warn_error].concat()
-        }
-    };
-
     let read_cmi_args = match module.get_interface() {
         Some(_) => {
             if is_interface {
@@ -481,10 +725,7 @@ fn compile_file(
         debug!("Compiling file: {}", &module_name);
 
         // TODO: Also read suffix from package-spec.
-        let suffix = match root_package.bsconfig.suffix.to_owned() {
-            Some(suffix) => suffix,
-            None => bsconfig::Suffix::Mjs,
-        };
+        let suffix = root_package.get_suffix();
 
         vec![
             "-bs-package-name".to_string(),
@@ -506,13 +747,7 @@ fn compile_file(
         namespace_args,
         read_cmi_args,
         vec!["-I".to_string(), ".".to_string()],
-        deps.concat(),
-        jsx_args,
-        jsx_module_args,
-        jsx_mode_args,
-        uncurried_args,
-        bsc_flags,
-        warning_args,
+        compiler_args(package, root_package, &build_state.packages, version),
         // vec!["-warn-error".to_string(), "A".to_string()],
         // ^^ this one fails for bisect-ppx
         // this is the default
@@ -527,7 +762,9 @@ fn compile_file(
     ]
     .concat();
 
-    let to_mjs = Command::new(helpers::get_bsc(&root_path))
+    let to_mjs = build_state
+        .compiler
+        .command()
         .current_dir(helpers::canonicalize_string_path(&build_path_abs.to_owned()).unwrap())
         .args(to_mjs_args)
         .output();
@@ -543,6 +780,7 @@ fn compile_file(
             let err = std::str::from_utf8(&x.stderr)
                 .expect("stdout should be non-null")
                 .to_string();
+            parse::write_ast_digest(ast_path);
 
             let dir = std::path::Path::new(implementation_file_path).parent().unwrap();
 
@@ -550,50 +788,34 @@ fn compile_file(
             if !is_interface {
                 let _ = std::fs::copy(
                     build_path_abs.to_string() + "/" + &module_name + ".cmi",
-                    std::path::Path::new(&helpers::get_bs_build_path(
-                        root_path,
-                        &package.name,
-                        package.is_root,
-                    ))
-                    .join(dir)
-                    // because editor tooling doesn't support namespace entries yet
-                    // we just remove the @ for now. This makes sure the editor support
-                    // doesn't break
-                    .join(module_name.to_owned().replace("@", "") + ".cmi"),
+                    std::path::Path::new(&helpers::get_bs_build_path(&package.build_dir))
+                        .join(dir)
+                        // because editor tooling doesn't support namespace entries yet
+                        // we just remove the @ for now. This makes sure the editor support
+                        // doesn't break
+                        .join(module_name.to_owned().replace("@", "") + ".cmi"),
                 );
                 let _ = std::fs::copy(
                     build_path_abs.to_string() + "/" + &module_name + ".cmj",
-                    std::path::Path::new(&helpers::get_bs_build_path(
-                        root_path,
-                        &package.name,
-                        package.is_root,
-                    ))
-                    .join(dir)
-                    .join(module_name.to_owned() + ".cmj"),
+                    std::path::Path::new(&helpers::get_bs_build_path(&package.build_dir))
+                        .join(dir)
+                        .join(module_name.to_owned() + ".cmj"),
                 );
                 let _ = std::fs::copy(
                     build_path_abs.to_string() + "/" + &module_name + ".cmt",
-                    std::path::Path::new(&helpers::get_bs_build_path(
-                        root_path,
-                        &package.name,
-                        package.is_root,
-                    ))
-                    .join(dir)
-                    // because editor tooling doesn't support namespace entries yet
-                    // we just remove the @ for now. This makes sure the editor support
-                    // doesn't break
-                    .join(module_name.to_owned().replace("@", "") + ".cmt"),
+                    std::path::Path::new(&helpers::get_bs_build_path(&package.build_dir))
+                        .join(dir)
+                        // because editor tooling doesn't support namespace entries yet
+                        // we just remove the @ for now. This makes sure the editor support
+                        // doesn't break
+                        .join(module_name.to_owned().replace("@", "") + ".cmt"),
                 );
             } else {
                 let _ = std::fs::copy(
                     build_path_abs.to_string() + "/" + &module_name + ".cmti",
-                    std::path::Path::new(&helpers::get_bs_build_path(
-                        root_path,
-                        &package.name,
-                        package.is_root,
-                    ))
-                    .join(dir)
-                    .join(module_name.to_owned() + ".cmti"),
+                    std::path::Path::new(&helpers::get_bs_build_path(&package.build_dir))
+                        .join(dir)
+                        .join(module_name.to_owned() + ".cmti"),
                 );
             }
             match &module.source_type {
@@ -609,34 +831,15 @@ fn compile_file(
                     // editor tools expects the source file in lib/bs for finding the current package
                     // and in lib/ocaml when referencing modules in other packages
                     let _ = std::fs::copy(
-                        std::path::Path::new(&helpers::get_package_path(
-                            root_path,
-                            &package.name,
-                            package.is_root,
-                        ))
-                        .join(path),
-                        std::path::Path::new(&helpers::get_bs_build_path(
-                            root_path,
-                            &package.name,
-                            package.is_root,
-                        ))
-                        .join(path),
+                        std::path::Path::new(&package.package_dir).join(path),
+                        std::path::Path::new(&helpers::get_bs_build_path(&package.build_dir)).join(path),
                     )
                     .expect("copying source file failed");
 
                     let _ = std::fs::copy(
-                        std::path::Path::new(&helpers::get_package_path(
-                            root_path,
-                            &package.name,
-                            package.is_root,
-                        ))
-                        .join(path),
-                        std::path::Path::new(&helpers::get_build_path(
-                            root_path,
-                            &package.name,
-                            package.is_root,
-                        ))
-                        .join(std::path::Path::new(path).file_name().unwrap()),
+                        std::path::Path::new(&package.package_dir).join(path),
+                        std::path::Path::new(&helpers::get_build_path(&package.build_dir))
+                            .join(std::path::Path::new(path).file_name().unwrap()),
                     )
                     .expect("copying source file failed");
                 }
@@ -668,6 +871,11 @@ pub fn mark_modules_with_deleted_deps_dirty(
     });
 }
 
+/// The last time the cmi or the cmj of a module changed, whichever is later.
+fn get_last_compiled_cmi_or_cmj(module: &Module) -> Option<SystemTime> {
+    module.last_compiled_cmi.max(module.last_compiled_cmj)
+}
+
 pub fn mark_modules_with_expired_deps_dirty(build_state: &mut BuildState) {
     let mut modules_with_expired_deps: AHashSet<String> = AHashSet::new();
     build_state
@@ -688,8 +896,13 @@ pub fn mark_modules_with_expired_deps_dirty(build_state: &mut BuildState) {
 
                         // we compare the last compiled time of the dependent module with the last
                         // compile of the interface of the module it depends on, if the interface
-                        // didn't change it doesn't matter
-                        match (dependent_module.last_compiled_cmt, module.last_compiled_cmi) {
+                        // didn't change it doesn't matter. The compiler doesn't write the cmi and cmj
+                        // again when they didn't change, and a dependent inlines values from the cmj,
+                        // so it is also expired when the cmj is newer
+                        match (
+                            dependent_module.last_compiled_cmt,
+                            get_last_compiled_cmi_or_cmj(module),
+                        ) {
                             (Some(last_compiled_dependent), Some(last_compiled)) => {
                                 if last_compiled_dependent < last_compiled {
                                     // println!(
@@ -716,7 +929,10 @@ pub fn mark_modules_with_expired_deps_dirty(build_state: &mut BuildState) {
                     SourceType::MlMap(_) => {
                         for dependent_of_namespace in dependent_module.dependents.iter() {
                             let dependent_module = build_state.modules.get(dependent_of_namespace).unwrap();
-                            match (dependent_module.last_compiled_cmt, module.last_compiled_cmi) {
+                            match (
+                                dependent_module.last_compiled_cmt,
+                                get_last_compiled_cmi_or_cmj(module),
+                            ) {
                                 (Some(last_compiled_dependent), Some(last_compiled)) => {
                                     if last_compiled_dependent < last_compiled {
                                         modules_with_expired_deps.insert(dependent.to_string());
@@ -735,3 +951,167 @@ pub fn mark_modules_with_expired_deps_dirty(build_state: &mut BuildState) {
         }
     });
 }
+
+#[cfg(test)]
+mod test {
+    use super::{get_critical_path_lengths, get_units, mark_modules_with_expired_deps_dirty, CompileUnit};
+    use crate::build::build_types::*;
+    use crate::build::compiler::Compiler;
+    use ahash::{AHashMap, AHashSet};
+    use std::time::{Duration, SystemTime};
+
+    fn create_module(deps: &[String], dependents: &[String]) -> Module {
+        Module {
+            source_type: SourceType::SourceFile(SourceFile {
+                implementation: Implementation {
+                    path: String::from("src/Module.res"),
+                    parse_state: ParseState::Success,
+                    compile_state: CompileState::Pending,
+                    last_modified: SystemTime::now(),
+                    dirty: true,
+                },
+                interface: None,
+            }),
+            deps: deps.iter().cloned().collect(),
+            dependents: dependents.iter().cloned().collect(),
+            package_name: String::from("package"),
+            compile_dirty: true,
+            last_compiled_cmi: None,
+            last_compiled_cmt: None,
+            last_compiled_cmj: None,
+        }
+    }
+
+    #[test]
+    fn test_critical_path_lengths_of_a_long_chain() {
+        // M0 <- M1 <- ... <- M49999, every module depends on the one before it
+        let length = 50_000;
+        let name = |index: usize| format!("M{}", index);
+        let mut build_state = BuildState::new(
+            String::from("/project"),
+            String::from("package"),
+            AHashMap::new(),
+            Compiler::default(),
+        );
+        for index in 0..length {
+            let deps = if index > 0 { vec![name(index - 1)] } else { vec![] };
+            let dependents = if index + 1 < length {
+                vec![name(index + 1)]
+            } else {
+                vec![]
+            };
+            build_state.insert_module(&name(index), create_module(&deps, &dependents));
+        }
+        let compile_universe = build_state.module_names.clone();
+        let units = compile_universe
+            .iter()
+            .flat_map(|module_name| get_units(module_name, build_state.get_module(module_name).unwrap()))
+            .collect::<Vec<CompileUnit>>();
+
+        let lengths = get_critical_path_lengths(&build_state, &compile_universe, &units);
+        assert_eq!(lengths[&CompileUnit::Implementation(name(0))], length);
+        assert_eq!(lengths[&CompileUnit::Implementation(name(length - 1))], 1);
+    }
+
+    #[test]
+    fn test_critical_path_lengths_with_a_cycle() {
+        let mut build_state = BuildState::new(
+            String::from("/project"),
+            String::from("package"),
+            AHashMap::new(),
+            Compiler::default(),
+        );
+        let a = String::from("A");
+        let b = String::from("B");
+        build_state.insert_module("A", create_module(&[b.clone()], &[b.clone()]));
+        build_state.insert_module("B", create_module(&[a.clone()], &[a.clone()]));
+        let compile_universe = AHashSet::from([a.clone(), b.clone()]);
+        let units = vec![CompileUnit::Implementation(a), CompileUnit::Implementation(b)];
+
+        let lengths = get_critical_path_lengths(&build_state, &compile_universe, &units);
+        assert_eq!(lengths.len(), 2);
+    }
+
+    #[test]
+    fn test_module_with_an_unchanged_ast_is_recompiled_when_a_dependency_has_a_newer_cmi() {
+        // only a comment in A changed, so the parser wrote the same AST again and A is not dirty itself,
+        // but B got a new cmi before the build was cancelled, and before A was compiled again
+        let last_compiled = SystemTime::now();
+        let mut build_state = BuildState::new(
+            String::from("/project"),
+            String::from("package"),
+            AHashMap::new(),
+            Compiler::default(),
+        );
+        let mut a = create_module(&[String::from("B")], &[]);
+        a.compile_dirty = false;
+        a.last_compiled_cmi = Some(last_compiled);
+        a.last_compiled_cmt = Some(last_compiled);
+        let mut b = create_module(&[], &[String::from("A")]);
+        b.compile_dirty = false;
+        b.last_compiled_cmi = Some(last_compiled + Duration::from_secs(1));
+        b.last_compiled_cmt = Some(last_compiled + Duration::from_secs(1));
+        build_state.insert_module("A", a);
+        build_state.insert_module("B", b);
+
+        mark_modules_with_expired_deps_dirty(&mut build_state);
+        assert!(build_state.get_module("A").unwrap().compile_dirty);
+        assert!(!build_state.get_module("B").unwrap().compile_dirty);
+    }
+
+    #[test]
+    fn test_cancelled_build_recompiles_the_dependents_of_a_new_cmj() {
+        // the first build compiled B, which only changed its cmj, and was cancelled before it compiled
+        // A again. The second build reads the compile assets from disk, so it only knows about the
+        // new cmj from its modification time.
+        let last_compiled = SystemTime::now();
+        let mut build_state = BuildState::new(
+            String::from("/project"),
+            String::from("package"),
+            AHashMap::new(),
+            Compiler::default(),
+        );
+        let mut a = create_module(&[String::from("B")], &[]);
+        a.compile_dirty = false;
+        a.last_compiled_cmi = Some(last_compiled);
+        a.last_compiled_cmt = Some(last_compiled);
+        a.last_compiled_cmj = Some(last_compiled);
+        let mut b = create_module(&[], &[String::from("A")]);
+        b.compile_dirty = false;
+        b.last_compiled_cmi = Some(last_compiled - Duration::from_secs(1));
+        b.last_compiled_cmt = Some(last_compiled + Duration::from_secs(1));
+        b.last_compiled_cmj = Some(last_compiled + Duration::from_secs(1));
+        build_state.insert_module("A", a);
+        build_state.insert_module("B", b);
+
+        mark_modules_with_expired_deps_dirty(&mut build_state);
+        assert!(build_state.get_module("A").unwrap().compile_dirty);
+        assert!(!build_state.get_module("B").unwrap().compile_dirty);
+    }
+
+    #[test]
+    fn test_dependents_of_an_unchanged_cmj_are_not_recompiled() {
+        let last_compiled = SystemTime::now();
+        let mut build_state = BuildState::new(
+            String::from("/project"),
+            String::from("package"),
+            AHashMap::new(),
+            Compiler::default(),
+        );
+        let mut a = create_module(&[String::from("B")], &[]);
+        a.compile_dirty = false;
+        a.last_compiled_cmi = Some(last_compiled);
+        a.last_compiled_cmt = Some(last_compiled);
+        a.last_compiled_cmj = Some(last_compiled);
+        let mut b = create_module(&[], &[String::from("A")]);
+        b.compile_dirty = false;
+        b.last_compiled_cmi = Some(last_compiled - Duration::from_secs(1));
+        b.last_compiled_cmt = Some(last_compiled + Duration::from_secs(1));
+        b.last_compiled_cmj = Some(last_compiled - Duration::from_secs(1));
+        build_state.insert_module("A", a);
+        build_state.insert_module("B", b);
+
+        mark_modules_with_expired_deps_dirty(&mut build_state);
+        assert!(!build_state.get_module("A").unwrap().compile_dirty);
+    }
+}
@@ -1,71 +1,254 @@
 use super::super::build_types::*;
 use crate::helpers;
-use ahash::AHashSet;
+use ahash::{AHashMap, AHashSet};
+use console::style;
+use std::collections::VecDeque;
 
-pub fn find(modules: &Vec<(&String, &Module)>) -> Vec<String> {
-    let mut visited: AHashSet<String> = AHashSet::new();
-    let mut stack: Vec<String> = vec![];
+/// Finds all the strongly connected components in a graph of `name -> dependencies` using
+/// Tarjan's algorithm. Only components that actually form a cycle (more than one node) are
+/// returned. The algorithm is iterative so that large module graphs don't overflow the stack.
+/// Both the components and their members are sorted, so we always report the same cycles.
+pub fn strongly_connected_components(graph: &AHashMap<String, Vec<String>>) -> Vec<Vec<String>> {
+    let mut names = graph.keys().collect::<Vec<&String>>();
+    names.sort();
 
-    // we want to sort the module names so that we always return the same
-    // dependency cycle (there can be more than one)
-    let mut module_names = modules
-        .iter()
-        .map(|(name, _)| name.to_string())
-        .collect::<Vec<String>>();
+    let mut index: AHashMap<&str, usize> = AHashMap::new();
+    let mut low_link: AHashMap<&str, usize> = AHashMap::new();
+    let mut on_stack: AHashSet<&str> = AHashSet::new();
+    let mut stack: Vec<&str> = vec![];
+    let mut components: Vec<Vec<String>> = vec![];
+    let mut next_index = 0;
 
-    module_names.sort_by(|a, b| a.cmp(b));
-    for module_name in module_names {
-        if find_dependency_cycle_helper(&module_name, &modules, &mut visited, &mut stack) {
-            return stack;
+    for name in names {
+        if index.contains_key(name.as_str()) {
+            continue;
+        }
+
+        // every frame holds the node and the position of the next edge to visit
+        let mut call_stack: Vec<(&str, usize)> = vec![(name, 0)];
+        index.insert(name, next_index);
+        low_link.insert(name, next_index);
+        next_index += 1;
+        stack.push(name);
+        on_stack.insert(name);
+
+        while let Some((node, edge)) = call_stack.pop() {
+            let edges = graph.get(node).map(|deps| deps.as_slice()).unwrap_or(&[]);
+            if let Some(dep) = edges.get(edge) {
+                call_stack.push((node, edge + 1));
+                // edges to nodes outside of the graph can't be part of a cycle
+                if !graph.contains_key(dep) {
+                    continue;
+                }
+                match index.get(dep.as_str()) {
+                    None => {
+                        index.insert(dep, next_index);
+                        low_link.insert(dep, next_index);
+                        next_index += 1;
+                        stack.push(dep);
+                        on_stack.insert(dep);
+                        call_stack.push((dep, 0));
+                    }
+                    Some(dep_index) if on_stack.contains(dep.as_str()) => {
+                        let low = low_link[node].min(*dep_index);
+                        low_link.insert(node, low);
+                    }
+                    Some(_) => (),
+                }
+                continue;
+            }
+
+            // all edges of this node are visited, propagate the low link to the parent
+            if let Some((parent, _)) = call_stack.last() {
+                let low = low_link[parent].min(low_link[node]);
+                low_link.insert(parent, low);
+            }
+
+            if low_link[node] == index[node] {
+                let mut component = vec![];
+                while let Some(member) = stack.pop() {
+                    on_stack.remove(member);
+                    component.push(member.to_string());
+                    if member == node {
+                        break;
+                    }
+                }
+                if component.len() > 1 {
+                    component.sort();
+                    components.push(component);
+                }
+            }
         }
-        visited.clear();
-        stack.clear();
     }
-    stack
+
+    components.sort();
+    components
 }
 
-fn find_dependency_cycle_helper(
-    module_name: &String,
-    modules: &Vec<(&String, &Module)>,
-    visited: &mut AHashSet<String>,
-    stack: &mut Vec<String>,
-) -> bool {
-    if let Some(module) = modules
-        .iter()
-        .find(|(name, _)| *name == module_name)
-        .map(|(_, module)| module)
-    {
-        visited.insert(module_name.to_string());
-        // if the module is a mlmap (namespace), we don't want to show this in the path
-        // because the namespace is not a module the user created, so only add source files
-        // to the stack
-        if let SourceType::SourceFile(_) = module.source_type {
-            stack.push(module_name.to_string())
-        }
-        for dep in &module.deps {
-            if !visited.contains(dep) {
-                if find_dependency_cycle_helper(dep, modules, visited, stack) {
-                    return true;
+/// Returns a single cycle through a strongly connected component. We start at the first member
+/// (sorted) and take the shortest path back to it, so the reported cycle is stable between runs.
+pub fn cycle_in_component(graph: &AHashMap<String, Vec<String>>, component: &[String]) -> Vec<String> {
+    let members = component.iter().collect::<AHashSet<&String>>();
+    let start = &component[0];
+    let mut parents: AHashMap<&String, &String> = AHashMap::new();
+    let mut queue: VecDeque<&String> = VecDeque::from([start]);
+
+    while let Some(node) = queue.pop_front() {
+        let mut deps = graph
+            .get(node)
+            .map(|deps| {
+                deps.iter()
+                    .filter(|dep| members.contains(dep))
+                    .collect::<Vec<&String>>()
+            })
+            .unwrap_or_default();
+        deps.sort();
+        for dep in deps {
+            if dep == start {
+                let mut cycle = vec![node.to_string()];
+                let mut current = node;
+                while let Some(parent) = parents.get(current) {
+                    cycle.push(parent.to_string());
+                    current = parent;
                 }
-            } else if stack.contains(dep) {
-                stack.push(dep.to_string());
-                return true;
+                cycle.reverse();
+                cycle.push(start.to_string());
+                return cycle;
+            }
+            if !parents.contains_key(dep) && dep != start {
+                parents.insert(dep, node);
+                queue.push_back(dep);
             }
         }
-        // because we only pushed source files to the stack, we also only need to
-        // pop these from the stack if we don't find a dependency cycle
-        if let SourceType::SourceFile(_) = module.source_type {
-            let _ = stack.pop();
-        }
-        return false;
     }
-    false
+
+    component.to_vec()
 }
 
-pub fn format(cycle: &Vec<String>) -> String {
-    cycle
+/// Finds every dependency cycle in the given modules. Every cycle starts and ends with the same
+/// module. Namespaces (mlmaps) are not shown in the path, because the namespace is not a module
+/// the user created.
+pub fn find(modules: &AHashMap<String, Module>) -> Vec<Vec<String>> {
+    let graph = modules
+        .iter()
+        .map(|(name, module)| {
+            let mut deps = module.deps.iter().cloned().collect::<Vec<String>>();
+            deps.sort();
+            (name.to_owned(), deps)
+        })
+        .collect::<AHashMap<String, Vec<String>>>();
+
+    strongly_connected_components(&graph)
+        .iter()
+        .map(|component| {
+            let cycle = cycle_in_component(&graph, component)
+                .into_iter()
+                .filter(|module_name| modules.get(module_name).map(|m| !m.is_mlmap()).unwrap_or(true))
+                .collect::<Vec<String>>();
+            // the first module might have been a namespace, so close the cycle again
+            match (cycle.first(), cycle.last()) {
+                (Some(first), Some(last)) if first != last => {
+                    let first = first.to_owned();
+                    cycle.into_iter().chain(std::iter::once(first)).collect()
+                }
+                _ => cycle,
+            }
+        })
+        .collect()
+}
+
+fn format_location(module_name: &str, build_state: &BuildState) -> String {
+    match build_state.get_module(module_name) {
+        Some(Module {
+            source_type: SourceType::SourceFile(source_file),
+            package_name,
+            ..
+        }) => format!("{} ({})", package_name, source_file.implementation.path),
+        Some(Module { package_name, .. }) => package_name.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+pub fn format(cycle: &[String], build_state: &BuildState) -> String {
+    let path = cycle
         .iter()
         .map(|s| helpers::format_namespaced_module_name(s))
         .collect::<Vec<String>>()
-        .join(" -> ")
+        .join(" -> ");
+
+    let edges = cycle
+        .windows(2)
+        .map(|edge| {
+            format!(
+                "  {} in {}\n    -> {} in {}",
+                helpers::format_namespaced_module_name(&edge[0]),
+                format_location(&edge[0], build_state),
+                helpers::format_namespaced_module_name(&edge[1]),
+                format_location(&edge[1], build_state),
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    format!("{}\n{}", path, edges)
+}
+
+pub fn format_error(cycles: &[Vec<String>], build_state: &BuildState) -> String {
+    format!(
+        "\n{}\n{}\n",
+        style("Can't continue... Found a circular dependency in your code:").red(),
+        cycles
+            .iter()
+            .map(|cycle| format(cycle, build_state))
+            .collect::<Vec<String>>()
+            .join("\n\n")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::strongly_connected_components;
+    use ahash::AHashMap;
+
+    fn graph(edges: &[(&str, &[&str])]) -> AHashMap<String, Vec<String>> {
+        edges
+            .iter()
+            .map(|(name, deps)| (name.to_string(), deps.iter().map(|d| d.to_string()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn test_strongly_connected_components_without_cycles() {
+        let graph = graph(&[("A", &["B", "C"]), ("B", &["C"]), ("C", &[])]);
+        assert!(strongly_connected_components(&graph).is_empty());
+    }
+
+    #[test]
+    fn test_strongly_connected_components_reports_every_cycle() {
+        let graph = graph(&[
+            ("A", &["B"]),
+            ("B", &["A", "C"]),
+            ("C", &["D"]),
+            ("D", &["E"]),
+            ("E", &["C", "Missing"]),
+            ("F", &["A"]),
+        ]);
+        assert_eq!(
+            strongly_connected_components(&graph),
+            vec![
+                vec!["A".to_string(), "B".to_string()],
+                vec!["C".to_string(), "D".to_string(), "E".to_string()]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cycle_in_component_closes_the_cycle() {
+        let graph = graph(&[("A", &["B"]), ("B", &["C"]), ("C", &["A"])]);
+        let components = strongly_connected_components(&graph);
+        assert_eq!(
+            super::cycle_in_component(&graph, &components[0]),
+            vec!["A", "B", "C", "A"]
+        );
+    }
 }
@@ -0,0 +1,183 @@
+use crate::bsconfig;
+use crate::helpers;
+use std::path::Path;
+use std::process::Command;
+
+pub const BSC_ENV_VAR: &str = "RESCRIPT_BSC_EXE";
+pub const LAUNCHER_ENV_VAR: &str = "REWATCH_LAUNCHER";
+
+/// The compiler binary, and the launcher that wraps every invocation of it.
+#[derive(Debug, Clone, Default)]
+pub struct Compiler {
+    pub bsc: String,
+    /// A command (with arguments) that bsc is run with, like `ccache` for C compilers
+    pub launcher: Vec<String>,
+}
+
+impl Compiler {
+    /// A command that runs bsc through the launcher, if there is one.
+    pub fn command(&self) -> Command {
+        match self.launcher.split_first() {
+            Some((launcher, launcher_args)) => {
+                let mut command = Command::new(launcher);
+                command.args(launcher_args).arg(&self.bsc);
+                command
+            }
+            None => Command::new(&self.bsc),
+        }
+    }
+}
+
+/// The folders in the rescript package that may contain the bsc binary for this platform. Older
+/// versions of ReScript only have a `linux` folder, which is also used on arm64.
+fn get_platform_folders() -> Result<Vec<&'static str>, String> {
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("macos", "aarch64") => Ok(vec!["darwinarm64"]),
+        ("macos", _) => Ok(vec!["darwin"]),
+        ("linux", "aarch64") => Ok(vec!["linuxarm64", "linux"]),
+        ("linux", _) => Ok(vec!["linux"]),
+        ("windows", _) => Ok(vec!["win32"]),
+        (os, arch) => Err(format!(
+            "There is no ReScript compiler for {}-{} in the rescript package. Set the path to bsc with \
+             --bsc, {}, or `bsc` in the rewatch section of the root config",
+            os, arch, BSC_ENV_VAR
+        )),
+    }
+}
+
+fn find_bsc(project_root: &str) -> Result<String, String> {
+    let platform_folders = get_platform_folders()?;
+    platform_folders
+        .iter()
+        .find_map(|folder| {
+            helpers::find_in_node_modules(&format!("rescript/{}/bsc.exe", folder), project_root)
+        })
+        .map(|bsc| bsc.to_string_lossy().to_string())
+        .ok_or(format!(
+            "Could not find bsc in node_modules/rescript/{} of {} or any of its parent directories. Is \
+             rescript installed? Otherwise set the path to bsc with --bsc, {}, or `bsc` in the rewatch \
+             section of the root config",
+            platform_folders.join(" or node_modules/rescript/"),
+            project_root,
+            BSC_ENV_VAR
+        ))
+}
+
+fn get_env(env_var: &str) -> Option<String> {
+    std::env::var(env_var)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+}
+
+/// bsc runs in the `lib/ocaml` folder of a package, so a relative path to it only works when it is
+/// made absolute first. A bare command (without a path separator) is looked up in the `PATH`.
+fn get_absolute_bsc(bsc: String, base_dir: &Path) -> String {
+    if bsc.contains('/') || bsc.contains(std::path::MAIN_SEPARATOR) {
+        let bsc = Path::new(&bsc);
+        base_dir
+            .join(bsc.strip_prefix(".").unwrap_or(bsc))
+            .to_string_lossy()
+            .to_string()
+    } else {
+        bsc
+    }
+}
+
+/// Resolves the compiler the same way as the jobs: the command line argument takes precedence over
+/// the environment variable, which takes precedence over the `bsc` and `launcher` settings in the
+/// `rewatch` section of the root config. A relative `bsc` on the command line or in the environment
+/// is relative to the current directory, in the config it is relative to the root. Without any of
+/// these, bsc is looked up in the rescript package in node_modules.
+pub fn get_compiler(
+    cli_bsc: Option<String>,
+    cli_launcher: Option<String>,
+    root_config: &bsconfig::T,
+    project_root: &str,
+) -> Result<Compiler, String> {
+    let config = root_config.rewatch.to_owned().unwrap_or_default();
+    let bsc = match cli_bsc.or_else(|| get_env(BSC_ENV_VAR)) {
+        Some(bsc) => {
+            let current_dir = std::env::current_dir()
+                .map_err(|e| format!("Could not read the current directory: {}", e))?;
+            get_absolute_bsc(bsc, &current_dir)
+        }
+        None => match config.bsc {
+            Some(bsc) => Path::new(project_root).join(bsc).to_string_lossy().to_string(),
+            None => find_bsc(project_root)?,
+        },
+    };
+    let launcher = match cli_launcher.or_else(|| get_env(LAUNCHER_ENV_VAR)) {
+        // on the command line and in the environment the arguments are separated by spaces
+        Some(launcher) => launcher.split_whitespace().map(str::to_string).collect(),
+        None => match config.launcher {
+            Some(bsconfig::OneOrMore::Multiple(launcher)) => launcher,
+            Some(bsconfig::OneOrMore::Single(launcher)) => vec![launcher],
+            None => vec![],
+        },
+    };
+    Ok(Compiler { bsc, launcher })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_absolute_bsc, get_compiler, Compiler};
+    use std::path::Path;
+
+    #[test]
+    fn test_command_runs_bsc_through_the_launcher() {
+        let compiler = Compiler {
+            bsc: "/nix/store/rescript/bin/bsc".to_string(),
+            launcher: vec!["nice".to_string(), "-n".to_string(), "10".to_string()],
+        };
+        let command = compiler.command();
+        assert_eq!(command.get_program(), "nice");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            vec!["-n", "10", "/nix/store/rescript/bin/bsc"]
+        );
+
+        let compiler = Compiler {
+            launcher: vec![],
+            ..compiler
+        };
+        let command = compiler.command();
+        assert_eq!(command.get_program(), "/nix/store/rescript/bin/bsc");
+        assert_eq!(command.get_args().count(), 0);
+    }
+
+    #[test]
+    fn test_relative_bsc_is_made_absolute() {
+        let current_dir = Path::new("/home/user/project");
+        assert_eq!(
+            get_absolute_bsc(String::from("./node_modules/.bin/bsc"), current_dir),
+            "/home/user/project/node_modules/.bin/bsc"
+        );
+        assert_eq!(
+            get_absolute_bsc(String::from("../rescript/bsc.exe"), current_dir),
+            "/home/user/project/../rescript/bsc.exe"
+        );
+        assert_eq!(
+            get_absolute_bsc(String::from("/nix/store/rescript/bin/bsc"), current_dir),
+            "/nix/store/rescript/bin/bsc"
+        );
+        assert_eq!(get_absolute_bsc(String::from("bsc"), current_dir), "bsc");
+    }
+
+    #[test]
+    fn test_launcher_arguments_from_the_config() {
+        let root_config = serde_json::from_str(
+            r#"{
+                "name": "root",
+                "sources": "src",
+                "rewatch": {"bsc": "bin/bsc", "launcher": ["/opt/my launcher/run", "--quiet"]}
+            }"#,
+        )
+        .unwrap();
+        let compiler = get_compiler(None, None, &root_config, "/home/user/project").unwrap();
+        assert_eq!(compiler.bsc, "/home/user/project/bin/bsc");
+        assert_eq!(compiler.launcher, vec!["/opt/my launcher/run", "--quiet"]);
+
+        let compiler = get_compiler(None, Some(String::from("nice -n 10")), &root_config, "/").unwrap();
+        assert_eq!(compiler.launcher, vec!["nice", "-n", "10"]);
+    }
+}
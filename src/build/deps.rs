@@ -1,7 +1,8 @@
 use super::build_types::*;
 use super::packages;
 use crate::helpers;
-use ahash::AHashSet;
+use ahash::{AHashMap, AHashSet};
+use console::style;
 use rayon::prelude::*;
 
 fn get_dep_modules(
@@ -74,16 +75,16 @@ pub fn get_deps(build_state: &mut BuildState, deleted_modules: &AHashSet<String>
         .par_iter()
         .map(|(module_name, module)| match &module.source_type {
             SourceType::MlMap(_) => (module_name.to_string(), module.deps.to_owned()),
+            // when the module failed to parse, there might not be an AST to read the dependencies
+            // from. The modules that depend on it still register themselves as dependents.
+            SourceType::SourceFile(_) if module.has_parse_error() => {
+                (module_name.to_string(), module.deps.to_owned())
+            }
             SourceType::SourceFile(source_file) => {
                 let package = build_state
                     .get_package(&module.package_name)
                     .expect("Package not found");
-                let ast_path = helpers::get_ast_path(
-                    &source_file.implementation.path,
-                    &module.package_name,
-                    &build_state.project_root,
-                    package.is_root,
-                );
+                let ast_path = helpers::get_ast_path(&source_file.implementation.path, &package.build_dir);
 
                 let mut deps = get_dep_modules(
                     &ast_path,
@@ -94,12 +95,7 @@ pub fn get_deps(build_state: &mut BuildState, deleted_modules: &AHashSet<String>
 
                 match &source_file.interface {
                     Some(interface) => {
-                        let iast_path = helpers::get_iast_path(
-                            &interface.path,
-                            &module.package_name,
-                            &build_state.project_root,
-                            package.is_root,
-                        );
+                        let iast_path = helpers::get_iast_path(&interface.path, &package.build_dir);
 
                         deps.extend(get_dep_modules(
                             &iast_path,
@@ -135,3 +131,162 @@ pub fn get_deps(build_state: &mut BuildState, deleted_modules: &AHashSet<String>
             });
         });
 }
+
+/// A module that uses a module of another package, while that package is not one of the
+/// `bs-dependencies` of its own package.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UndeclaredDependency {
+    pub package_name: String,
+    pub dependency_package_name: String,
+    pub module_name: String,
+    pub dependency_module_name: String,
+}
+
+fn get_declared_dependencies(package: &packages::Package) -> AHashSet<String> {
+    package
+        .bsconfig
+        .bs_dependencies
+        .to_owned()
+        .unwrap_or_default()
+        .into_iter()
+        .collect()
+}
+
+/// Maps every dependency of every module to the package it belongs to, and returns the ones that
+/// cross into a package that is not declared as a dependency. Those are only found on the include
+/// path by accident (or not at all), so they should be declared.
+pub fn get_undeclared_dependencies(build_state: &BuildState) -> Vec<UndeclaredDependency> {
+    let mut undeclared_dependencies = build_state
+        .modules
+        .iter()
+        .flat_map(|(module_name, module)| {
+            let package = build_state
+                .get_package(&module.package_name)
+                .expect("Package not found");
+            let declared_dependencies = get_declared_dependencies(package);
+            module.deps.iter().filter_map(move |dep| {
+                let dep_module = build_state.get_module(dep)?;
+                if dep_module.package_name == module.package_name
+                    || declared_dependencies.contains(&dep_module.package_name)
+                {
+                    return None;
+                }
+                Some(UndeclaredDependency {
+                    package_name: module.package_name.to_owned(),
+                    dependency_package_name: dep_module.package_name.to_owned(),
+                    module_name: module_name.to_owned(),
+                    dependency_module_name: dep.to_owned(),
+                })
+            })
+        })
+        .collect::<Vec<UndeclaredDependency>>();
+    undeclared_dependencies.sort();
+    undeclared_dependencies
+}
+
+/// The packages in `bs-dependencies` of which no module is used. Only the root package and the
+/// pinned dependencies are checked, those are the packages that are developed in this project.
+/// Packages without modules of their own are skipped: in a monorepo the root package often only
+/// lists the packages to build, and in a focused build the packages outside the focus have none.
+pub fn get_unused_dependencies(build_state: &BuildState) -> Vec<(String, String)> {
+    let mut used_dependencies: AHashMap<&str, AHashSet<&str>> = AHashMap::new();
+    let packages_with_modules = build_state
+        .modules
+        .values()
+        .filter(|module| !module.is_mlmap())
+        .map(|module| module.package_name.as_str())
+        .collect::<AHashSet<&str>>();
+    build_state.modules.values().for_each(|module| {
+        module
+            .deps
+            .iter()
+            .filter_map(|dep| build_state.get_module(dep))
+            .for_each(|dep_module| {
+                used_dependencies
+                    .entry(&module.package_name)
+                    .or_default()
+                    .insert(&dep_module.package_name);
+            })
+    });
+
+    let mut unused_dependencies = build_state
+        .packages
+        .values()
+        .filter(|package| package.is_root || package.is_pinned_dep)
+        .filter(|package| packages_with_modules.contains(package.name.as_str()))
+        .flat_map(|package| {
+            let used = used_dependencies.get(package.name.as_str());
+            get_declared_dependencies(package)
+                .into_iter()
+                .filter(move |dependency| {
+                    !used
+                        .map(|used| used.contains(dependency.as_str()))
+                        .unwrap_or(false)
+                })
+                .map(move |dependency| (package.name.to_owned(), dependency))
+        })
+        .collect::<Vec<(String, String)>>();
+    unused_dependencies.sort();
+    unused_dependencies
+}
+
+pub fn format_undeclared_dependencies(undeclared_dependencies: &[UndeclaredDependency]) -> String {
+    let mut by_package: Vec<((&str, &str), Vec<String>)> = vec![];
+    for undeclared in undeclared_dependencies {
+        let key = (
+            undeclared.package_name.as_str(),
+            undeclared.dependency_package_name.as_str(),
+        );
+        let usage = format!(
+            "{} uses {}",
+            helpers::format_namespaced_module_name(&undeclared.module_name),
+            helpers::format_namespaced_module_name(&undeclared.dependency_module_name)
+        );
+        match by_package.last_mut() {
+            Some((last_key, usages)) if *last_key == key => usages.push(usage),
+            _ => by_package.push((key, vec![usage])),
+        }
+    }
+
+    format!(
+        "\n{}\n{}\n",
+        style("Can't continue... Found modules that use packages that are not declared as dependencies:")
+            .red(),
+        by_package
+            .iter()
+            .map(|((package_name, dependency_package_name), usages)| {
+                format!(
+                    "{} uses {}, add \"{}\" to the bs-dependencies of {}\n{}",
+                    style(package_name).bold(),
+                    style(dependency_package_name).bold(),
+                    dependency_package_name,
+                    package_name,
+                    usages
+                        .iter()
+                        .map(|usage| format!("  {}", usage))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n\n")
+    )
+}
+
+pub fn format_unused_dependencies(unused_dependencies: &[(String, String)]) -> String {
+    format!(
+        "\n{}\n{}\n",
+        style("Warning: Found dependencies that none of the modules of the package use:").yellow(),
+        unused_dependencies
+            .iter()
+            .map(|(package_name, dependency)| {
+                format!(
+                    "{} declares {} in bs-dependencies, but none of its modules use it",
+                    style(package_name).bold(),
+                    style(dependency).bold()
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    )
+}
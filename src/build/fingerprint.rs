@@ -0,0 +1,142 @@
+use super::build_types::*;
+use super::compile;
+use super::packages::{self, Package};
+use super::parse;
+use super::version::Version;
+use crate::bsconfig;
+use crate::helpers;
+use ahash::{AHashMap, AHashSet};
+use log::debug;
+use std::fs;
+
+/// The ppx binaries of a package. The arguments of a ppx are left out, those are already part of
+/// the arguments for the parser.
+fn get_ppx_paths(package: &Package, project_root: &str) -> Vec<String> {
+    let ppx_binaries = package.bsconfig.ppx_flags.as_ref().map(|ppx_flags| {
+        ppx_flags
+            .iter()
+            .filter_map(|ppx_flag| match ppx_flag {
+                bsconfig::OneOrMore::Single(ppx) => Some(bsconfig::OneOrMore::Single(ppx.to_owned())),
+                bsconfig::OneOrMore::Multiple(ppx) => {
+                    ppx.first().map(|ppx| bsconfig::OneOrMore::Single(ppx.to_owned()))
+                }
+            })
+            .collect::<Vec<bsconfig::OneOrMore<String>>>()
+    });
+
+    bsconfig::flatten_ppx_flags(
+        &helpers::get_node_modules_path(project_root),
+        &ppx_binaries,
+        &package.package_dir,
+    )
+    .into_iter()
+    .filter(|arg| arg != "-ppx")
+    .collect()
+}
+
+/// The fingerprint of everything apart from the sources that determines the output of a package:
+/// the compiler version, the arguments for the parser and the compiler, the ppx binaries, and for
+/// prebuilt dependencies the installed version of the package.
+pub fn get_fingerprint(
+    package: &Package,
+    root_package: &Package,
+    packages: &AHashMap<String, Package>,
+    project_root: &str,
+    version: &Version,
+) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(version.to_string().as_bytes());
+    // a prebuilt dependency is rebuilt completely when it is updated
+    if package.is_prebuilt_dependency() {
+        hasher.update(packages::get_package_identity(package).as_bytes());
+    }
+
+    let parser_args = parse::parser_args(package, root_package, project_root, version);
+    let compiler_args = compile::compiler_args(package, root_package, packages, version);
+    for arg in parser_args.iter().chain(compiler_args.iter()) {
+        hasher.update(arg.as_bytes());
+        // separate the arguments, so `["-w", "a"]` and `["-wa"]` have a different fingerprint
+        hasher.update(&[0]);
+    }
+
+    for ppx_path in get_ppx_paths(package, project_root) {
+        match helpers::compute_file_hash(&ppx_path) {
+            Some(hash) => hasher.update(hash.as_bytes()),
+            None => hasher.update(ppx_path.as_bytes()),
+        };
+    }
+
+    hasher.finalize().to_hex().to_string()
+}
+
+pub fn get_fingerprints(build_state: &BuildState, version: &Version) -> AHashMap<String, String> {
+    let root_package = build_state
+        .get_package(&build_state.root_config_name)
+        .expect("Could not find root package");
+    build_state
+        .packages
+        .values()
+        .map(|package| {
+            (
+                package.name.to_owned(),
+                get_fingerprint(
+                    package,
+                    root_package,
+                    &build_state.packages,
+                    &build_state.project_root,
+                    version,
+                ),
+            )
+        })
+        .collect()
+}
+
+fn read_fingerprint(package: &Package) -> Option<String> {
+    fs::read_to_string(helpers::get_fingerprint_path(&package.build_dir)).ok()
+}
+
+/// Marks all the modules of the packages of which the fingerprint changed since the last successful
+/// build as dirty, so they are parsed and compiled again. Packages that don't have a fingerprint
+/// yet are rebuilt as well, because we don't know what their artifacts were built with. Returns
+/// the names of the packages that changed.
+pub fn mark_changed_packages_dirty(
+    build_state: &mut BuildState,
+    fingerprints: &AHashMap<String, String>,
+) -> AHashSet<String> {
+    let changed_packages = build_state
+        .packages
+        .values()
+        .filter(|package| read_fingerprint(package).as_ref() != fingerprints.get(&package.name))
+        .map(|package| package.name.to_owned())
+        .collect::<AHashSet<String>>();
+
+    build_state
+        .modules
+        .values_mut()
+        .filter(|module| changed_packages.contains(&module.package_name))
+        .for_each(|module| {
+            module.compile_dirty = true;
+            match &mut module.source_type {
+                SourceType::SourceFile(source_file) => {
+                    source_file.implementation.dirty = true;
+                    if let Some(interface) = source_file.interface.as_mut() {
+                        interface.dirty = true;
+                    }
+                }
+                SourceType::MlMap(mlmap) => mlmap.dirty = true,
+            }
+        });
+
+    debug!("Packages with a changed fingerprint: {:?}", changed_packages);
+    changed_packages
+}
+
+/// Stores the fingerprints with the artifacts. This should only be called when all modules were
+/// compiled, so the changed packages are rebuilt completely until a build succeeds.
+pub fn write_fingerprints(build_state: &BuildState, fingerprints: &AHashMap<String, String>) {
+    build_state.get_built_packages().into_iter().for_each(|package| {
+        if let Some(fingerprint) = fingerprints.get(&package.name) {
+            let _ = fs::write(helpers::get_fingerprint_path(&package.build_dir), fingerprint);
+        }
+    });
+}
@@ -0,0 +1,60 @@
+use crate::bsconfig;
+use log::warn;
+
+pub const JOBS_ENV_VAR: &str = "REWATCH_JOBS";
+pub const WATCH_JOBS_ENV_VAR: &str = "REWATCH_WATCH_JOBS";
+
+fn get_env_jobs(env_var: &str) -> Option<usize> {
+    match std::env::var(env_var) {
+        Ok(value) => match value.trim().parse::<usize>() {
+            Ok(jobs) if jobs > 0 => Some(jobs),
+            _ => {
+                warn!("Ignoring {}={}, expected a number greater than 0", env_var, value);
+                None
+            }
+        },
+        Err(_) => None,
+    }
+}
+
+fn get_available_parallelism() -> usize {
+    std::thread::available_parallelism()
+        .map(|parallelism| parallelism.get())
+        .unwrap_or(1)
+}
+
+/// The number of compiler processes that may run at the same time. The command line argument
+/// takes precedence over the environment variable, which takes precedence over the `jobs` setting
+/// in the `rewatch` section of the root config. By default, we use all available cores.
+pub fn get_jobs(cli_jobs: Option<usize>, root_config: &bsconfig::T) -> Option<usize> {
+    let config = root_config.rewatch.to_owned().unwrap_or_default();
+    cli_jobs
+        .filter(|jobs| *jobs > 0)
+        .or_else(|| get_env_jobs(JOBS_ENV_VAR))
+        .or(config.jobs.filter(|jobs| *jobs > 0))
+}
+
+/// The number of compiler processes that may run at the same time in watch mode. Uses the same
+/// precedence as `get_jobs`, and falls back to the regular jobs setting. When nothing is
+/// configured, we only use half of the cores, so the editor stays responsive.
+pub fn get_watch_jobs(
+    cli_watch_jobs: Option<usize>,
+    cli_jobs: Option<usize>,
+    root_config: &bsconfig::T,
+) -> usize {
+    let config = root_config.rewatch.to_owned().unwrap_or_default();
+    cli_watch_jobs
+        .filter(|jobs| *jobs > 0)
+        .or_else(|| get_env_jobs(WATCH_JOBS_ENV_VAR))
+        .or(config.watch_jobs.filter(|jobs| *jobs > 0))
+        .or_else(|| get_jobs(cli_jobs, root_config))
+        .unwrap_or_else(|| (get_available_parallelism() / 2).max(1))
+}
+
+/// Every parse and compile task spawns a single bsc process on the rayon thread pool, so limiting
+/// the number of threads limits the number of concurrent bsc processes.
+pub fn initialize_thread_pool(jobs: usize) {
+    if let Err(e) = rayon::ThreadPoolBuilder::new().num_threads(jobs).build_global() {
+        warn!("Could not limit the number of jobs to {}: {}", jobs, e);
+    }
+}
@@ -12,10 +12,10 @@ enum Location {
     Ocaml,
 }
 
-fn get_log_file_path(project_root: &str, subfolder: Location, name: &str, is_root: bool) -> String {
+fn get_log_file_path(package: &Package, subfolder: Location) -> String {
     let build_folder = match subfolder {
-        Location::Bs => helpers::get_bs_build_path(project_root, name, is_root),
-        Location::Ocaml => helpers::get_build_path(project_root, name, is_root),
+        Location::Bs => helpers::get_bs_build_path(&package.build_dir),
+        Location::Ocaml => helpers::get_build_path(&package.build_dir),
     };
 
     build_folder.to_owned() + "/.compiler.log"
@@ -44,45 +44,32 @@ fn write_to_log_file(mut file: File, package_name: &str, content: &str) {
     }
 }
 
-pub fn initialize(project_root: &str, packages: &AHashMap<String, Package>) {
+pub fn initialize(packages: &AHashMap<String, Package>) {
     packages.par_iter().for_each(|(name, package)| {
-        let _ = File::create(get_log_file_path(
-            project_root,
-            Location::Bs,
-            name,
-            package.is_root,
-        ))
-        .map(|file| write_to_log_file(file, &name, &format!("#Start({})\n", helpers::get_system_time())))
-        .expect(&("Cannot create compiler log for package ".to_owned() + name));
+        let _ = File::create(get_log_file_path(package, Location::Bs))
+            .map(|file| write_to_log_file(file, &name, &format!("#Start({})\n", helpers::get_system_time())))
+            .expect(&("Cannot create compiler log for package ".to_owned() + name));
     })
 }
 
-pub fn append(project_root: &str, is_root: bool, name: &str, str: &str) {
+pub fn append(package: &Package, str: &str) {
     File::options()
         .append(true)
-        .open(get_log_file_path(project_root, Location::Bs, name, is_root))
-        .map(|file| write_to_log_file(file, &name, str))
-        .expect(
-            &("Cannot write compilerlog: ".to_owned()
-                + &get_log_file_path(project_root, Location::Bs, name, is_root)),
-        );
+        .open(get_log_file_path(package, Location::Bs))
+        .map(|file| write_to_log_file(file, &package.name, str))
+        .expect(&("Cannot write compilerlog: ".to_owned() + &get_log_file_path(package, Location::Bs)));
 }
 
-pub fn finalize(project_root: &str, packages: &AHashMap<String, Package>) {
+pub fn finalize(packages: &AHashMap<String, Package>) {
     packages.par_iter().for_each(|(name, package)| {
         let _ = File::options()
             .append(true)
-            .open(get_log_file_path(
-                project_root,
-                Location::Bs,
-                name,
-                package.is_root,
-            ))
+            .open(get_log_file_path(package, Location::Bs))
             .map(|file| write_to_log_file(file, &name, &format!("#Done({})\n", helpers::get_system_time())));
 
         let _ = std::fs::copy(
-            get_log_file_path(project_root, Location::Bs, name, package.is_root),
-            get_log_file_path(project_root, Location::Ocaml, name, package.is_root),
+            get_log_file_path(package, Location::Bs),
+            get_log_file_path(package, Location::Ocaml),
         );
     })
 }
@@ -1,9 +1,9 @@
+use crate::build::compiler::Compiler;
 use crate::build::packages;
 use crate::helpers;
 use ahash::AHashSet;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::process::Command;
 
 // Namespaces work like the following: The build system will generate a file
 // called `MyModule.mlmap` which contains all modules that are in the namespace
@@ -23,18 +23,14 @@ pub fn gen_mlmap(
     package: &packages::Package,
     namespace: &str,
     depending_modules: &AHashSet<String>,
-    root_path: &str,
 ) -> String {
-    let build_path_abs = helpers::get_build_path(root_path, &package.name, package.is_root);
+    let build_path_abs = helpers::get_build_path(&package.build_dir);
     // we don't really need to create a digest, because we track if we need to
     // recompile in a different way but we need to put it in the file for it to
     // be readable.
 
     let path = build_path_abs.to_string() + "/" + namespace + ".mlmap";
-    let mut file = File::create(&path).expect("Unable to create mlmap");
-
-    file.write_all(b"randjbuildsystem\n" as &[u8])
-        .expect("Unable to write mlmap");
+    let mut contents = "randjbuildsystem\n".to_string();
 
     let mut modules = Vec::from_iter(depending_modules.to_owned());
     modules.sort();
@@ -43,21 +39,150 @@ pub fn gen_mlmap(
         // (only contains A-Z a-z 0-9 and _ and only starts with a capital letter)
         // if not, it does not make sense to export as part of the name space
         // this helps compile times of exotic modules such as MyModule.test
-        file.write_all(module.as_bytes()).unwrap();
-        file.write_all(b"\n").unwrap();
+        contents.push_str(&module);
+        contents.push('\n');
+    }
+
+    // we only write the mlmap when the modules in the namespace changed, so the modification time
+    // tells if the mlmap needs to be compiled again
+    if fs::read_to_string(&path).ok().as_ref() != Some(&contents) {
+        let mut file = File::create(&path).expect("Unable to create mlmap");
+        file.write_all(contents.as_bytes())
+            .expect("Unable to write mlmap");
     }
 
     path.to_string()
 }
 
-pub fn compile_mlmap(package: &packages::Package, namespace: &str, root_path: &str) {
-    let build_path_abs = helpers::get_build_path(root_path, &package.name, package.is_root);
+/// Whether the mlmap was compiled after it was written for the last time. When it was never
+/// compiled, or the modules in the namespace changed since, it needs to be compiled again.
+pub fn is_mlmap_compiled(mlmap_path: &str, compile_path: &str) -> bool {
+    let last_modified = |path: &str| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    match (last_modified(mlmap_path), last_modified(compile_path)) {
+        (Some(mlmap_last_modified), Some(last_compiled)) => last_compiled > mlmap_last_modified,
+        _ => false,
+    }
+}
+
+pub fn compile_mlmap(package: &packages::Package, namespace: &str, compiler: &Compiler) {
+    let build_path_abs = helpers::get_build_path(&package.build_dir);
     let mlmap_name = format!("{}.mlmap", namespace);
     let args = vec!["-w", "-49", "-color", "always", "-no-alias-deps", &mlmap_name];
 
-    let _ = Command::new(helpers::get_bsc(&root_path))
+    let _ = compiler
+        .command()
         .current_dir(helpers::canonicalize_string_path(&build_path_abs).unwrap())
         .args(args)
         .output()
         .expect("err");
 }
+
+#[cfg(test)]
+mod test {
+    use super::{gen_mlmap, is_mlmap_compiled};
+    use crate::build::packages::{Namespace, Package};
+    use crate::helpers;
+    use ahash::AHashSet;
+    use std::fs::{self, File};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    fn create_package(build_dir: &Path) -> Package {
+        Package {
+            name: String::from("package"),
+            bsconfig: serde_json::from_str(r#"{"name": "package", "sources": "src", "namespace": true}"#)
+                .unwrap(),
+            source_folders: AHashSet::new(),
+            source_files: None,
+            namespace: Namespace::Namespace(String::from("Package")),
+            modules: None,
+            package_dir: build_dir.to_string_lossy().to_string(),
+            build_dir: build_dir.to_string_lossy().to_string(),
+            dirs: None,
+            is_pinned_dep: false,
+            is_root: true,
+        }
+    }
+
+    fn set_modified(path: &str, seconds: u64) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    /// Writes the mlmap of a namespace with the modules `A` and `B`, and compiles it.
+    fn compiled_mlmap(package: &Package) -> (String, String) {
+        fs::create_dir_all(helpers::get_build_path(&package.build_dir)).unwrap();
+        let modules = AHashSet::from([String::from("A"), String::from("B")]);
+        let mlmap_path = gen_mlmap(package, "Package", &modules);
+        let compile_path = helpers::get_mlmap_compile_path(&package.build_dir, "Package");
+        fs::write(&compile_path, "").unwrap();
+        set_modified(&mlmap_path, 1_000);
+        set_modified(&compile_path, 2_000);
+        assert!(is_mlmap_compiled(&mlmap_path, &compile_path));
+        (mlmap_path, compile_path)
+    }
+
+    #[test]
+    fn test_unchanged_namespace_is_not_written_or_compiled_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let package = create_package(dir.path());
+        let (mlmap_path, compile_path) = compiled_mlmap(&package);
+
+        gen_mlmap(
+            &package,
+            "Package",
+            &AHashSet::from([String::from("B"), String::from("A")]),
+        );
+        assert_eq!(
+            fs::metadata(&mlmap_path).unwrap().modified().unwrap(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_000)
+        );
+        assert!(is_mlmap_compiled(&mlmap_path, &compile_path));
+    }
+
+    #[test]
+    fn test_namespace_with_an_added_module_is_written_and_compiled_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let package = create_package(dir.path());
+        let (mlmap_path, compile_path) = compiled_mlmap(&package);
+
+        gen_mlmap(
+            &package,
+            "Package",
+            &AHashSet::from([String::from("A"), String::from("B"), String::from("C")]),
+        );
+        assert_eq!(
+            fs::read_to_string(&mlmap_path).unwrap(),
+            "randjbuildsystem\nA\nB\nC\n"
+        );
+        assert!(!is_mlmap_compiled(&mlmap_path, &compile_path));
+    }
+
+    #[test]
+    fn test_namespace_with_a_removed_module_is_written_and_compiled_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let package = create_package(dir.path());
+        let (mlmap_path, compile_path) = compiled_mlmap(&package);
+
+        gen_mlmap(&package, "Package", &AHashSet::from([String::from("A")]));
+        assert_eq!(fs::read_to_string(&mlmap_path).unwrap(), "randjbuildsystem\nA\n");
+        assert!(!is_mlmap_compiled(&mlmap_path, &compile_path));
+    }
+
+    #[test]
+    fn test_mlmap_that_was_never_compiled_is_compiled() {
+        let dir = tempfile::tempdir().unwrap();
+        let package = create_package(dir.path());
+        fs::create_dir_all(helpers::get_build_path(&package.build_dir)).unwrap();
+        let mlmap_path = gen_mlmap(&package, "Package", &AHashSet::from([String::from("A")]));
+
+        assert!(!is_mlmap_compiled(
+            &mlmap_path,
+            &helpers::get_mlmap_compile_path(&package.build_dir, "Package")
+        ));
+    }
+}
@@ -1,13 +1,16 @@
+use super::boundaries;
 use super::build_types::*;
+use super::compile::dependency_cycle;
 use super::namespaces;
 use super::packages;
+use super::version::{Capability, Version};
 use crate::bsconfig;
 use crate::helpers;
 use crate::helpers::emojis::*;
 use ahash::{AHashMap, AHashSet};
 use console::style;
 use convert_case::{Case, Casing};
-use log::{debug, error};
+use log::debug;
 use rayon::prelude::*;
 use std::error;
 use std::fs::{self};
@@ -46,8 +49,11 @@ pub struct Package {
     pub source_files: Option<AHashMap<String, SourceFileMeta>>,
     pub namespace: Namespace,
     pub modules: Option<AHashSet<String>>,
-    // canonicalized dir of the package
+    // the real (canonicalized) dir of the package, see `resolve_package_dir`
     pub package_dir: String,
+    // the dir in which the `lib` folder with the build artifacts is. This is the package dir,
+    // unless the package is a dependency that is built out of tree, see `set_build_dirs`
+    pub build_dir: String,
     pub dirs: Option<AHashSet<PathBuf>>,
     pub is_pinned_dep: bool,
     pub is_root: bool,
@@ -164,15 +170,24 @@ fn get_source_dirs(source: bsconfig::Source, sub_path: Option<PathBuf>) -> AHash
     source_folders
 }
 
-fn get_package_dir(package_name: &str, is_root: bool) -> String {
-    if is_root {
-        "".to_string()
-    } else {
-        helpers::get_relative_package_path(&package_name, is_root)
-    }
+/// Resolves the directory of a dependency the way Node does: we look for `node_modules/<name>` in
+/// the directory of the package that depends on it, and then in each of its parents. Symlinks
+/// (like the ones in the `.pnpm` store, or workspace packages) are followed to the real path, so
+/// the dependencies of the dependency are resolved from there.
+pub fn resolve_package_dir(package_name: &str, dependent_dir: &str) -> Option<String> {
+    helpers::find_in_node_modules(package_name, dependent_dir)
+        .filter(|package_dir| package_dir.is_dir())
+        .and_then(|package_dir| package_dir.canonicalize().ok())
+        .map(|package_dir| package_dir.to_string_lossy().to_string())
+}
+
+pub fn read_bsconfig(package_dir: &str) -> Result<bsconfig::T, String> {
+    read_bsconfig_with_warnings(package_dir).map(|(bsconfig, _)| bsconfig)
 }
 
-fn read_bsconfig(package_dir: &str) -> bsconfig::T {
+/// Reads the config of the package, together with the warnings about it. Only `make` reports the
+/// warnings, the other places that read a config drop them, so they are printed once per build.
+pub fn read_bsconfig_with_warnings(package_dir: &str) -> Result<(bsconfig::T, Vec<String>), String> {
     let prefix = if package_dir == "" {
         "".to_string()
     } else {
@@ -189,18 +204,139 @@ fn read_bsconfig(package_dir: &str) -> bsconfig::T {
     }
 }
 
+fn has_bsconfig(dir: &Path) -> bool {
+    dir.join("rescript.json").exists() || dir.join("bsconfig.json").exists()
+}
+
+/// The `workspaces` of the `package.json` in the directory, either the list itself or the
+/// `packages` of the Yarn style object.
+fn get_workspaces(dir: &Path) -> Vec<String> {
+    fs::read_to_string(dir.join("package.json"))
+        .ok()
+        .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok())
+        .and_then(|package_json| {
+            let workspaces = package_json.get("workspaces")?;
+            workspaces.get("packages").unwrap_or(workspaces).as_array().cloned()
+        })
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|workspace| workspace.as_str())
+        .map(|workspace| workspace.trim_start_matches("./").trim_end_matches('/').to_string())
+        .collect()
+}
+
+fn is_workspace_member(workspace_root: &Path, package_dir: &Path) -> bool {
+    match package_dir.strip_prefix(workspace_root).ok().and_then(|path| path.to_str()) {
+        Some(path) => get_workspaces(workspace_root)
+            .iter()
+            .any(|workspace| boundaries::glob_match(workspace, path)),
+        None => false,
+    }
+}
+
+/// Finds the root of the project from a directory inside of it. The nearest directory with a config
+/// is the current package. From there we walk up, and every parent of which the config depends on
+/// the package (or of which the `package.json` lists it in its `workspaces`) takes its place, until
+/// we leave the project through a `node_modules` folder. Returns the root, and the name of the
+/// current package to focus the build on when that is not the root itself.
+pub fn find_project_root(dir: &str) -> Result<(String, Option<String>), String> {
+    let dir = PathBuf::from(dir)
+        .canonicalize()
+        .map_err(|e| format!("Could not read {}: {}", dir, e))?;
+    let package_dir = dir.ancestors().find(|dir| has_bsconfig(dir)).ok_or(format!(
+        "Could not find a rescript.json or bsconfig.json in {} or any of its parent directories",
+        dir.to_string_lossy()
+    ))?;
+    let package_name = read_bsconfig(&package_dir.to_string_lossy())?.name;
+
+    let (mut root_dir, mut root_name) = (package_dir, package_name.to_owned());
+    for parent in package_dir
+        .ancestors()
+        .skip(1)
+        .take_while(|parent| parent.file_name().map(|name| name != "node_modules").unwrap_or(true))
+        .filter(|parent| has_bsconfig(parent))
+    {
+        let bsconfig = match read_bsconfig(&parent.to_string_lossy()) {
+            Ok(bsconfig) => bsconfig,
+            Err(_) => continue,
+        };
+        let depends_on_root = bsconfig
+            .bs_dependencies
+            .as_ref()
+            .map(|dependencies| dependencies.contains(&root_name))
+            .unwrap_or(false);
+        if depends_on_root || is_workspace_member(parent, root_dir) {
+            (root_dir, root_name) = (parent, bsconfig.name);
+        }
+    }
+
+    let focus = if root_dir == package_dir {
+        None
+    } else {
+        Some(package_name)
+    };
+    Ok((root_dir.to_string_lossy().to_string(), focus))
+}
+
+/// The package to focus the build on has to be in the package tree of the root. It isn't when the
+/// root was found through the `workspaces` in its `package.json`, but its config doesn't list the
+/// package in its dependencies. Without this check, nothing would be built.
+pub fn validate_focus(
+    packages: &AHashMap<String, Package>,
+    focus: &Option<String>,
+    root_config_name: &str,
+) -> Result<(), String> {
+    match focus {
+        Some(focus) if !packages.contains_key(focus) => Err(format!(
+            "{} is in the workspace of {} but not one of its dependencies, add \"{}\" to the \
+             bs-dependencies of {} to build it",
+            focus, root_config_name, focus, root_config_name
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// The focus package, and all the packages it depends on.
+pub fn get_focus_packages(packages: &AHashMap<String, Package>, focus: &str) -> AHashSet<String> {
+    let mut focus_packages = AHashSet::new();
+    let mut queue = vec![focus.to_string()];
+    while let Some(package_name) = queue.pop() {
+        if let Some(package) = packages.get(&package_name) {
+            if focus_packages.insert(package_name) {
+                queue.extend(package.bsconfig.bs_dependencies.to_owned().unwrap_or_default());
+            }
+        }
+    }
+    focus_packages
+}
+
+/// Drops the modules of the packages outside of the focus, so only the focus package and its
+/// dependencies are built. This happens after cleaning up the previous build, so the compile assets
+/// of the other packages are left alone.
+pub fn retain_focus_modules(build_state: &mut BuildState, focus: &str) {
+    let focus_packages = get_focus_packages(&build_state.packages, focus);
+    build_state
+        .modules
+        .retain(|_, module| focus_packages.contains(&module.package_name));
+    build_state
+        .module_names
+        .retain(|module_name| build_state.modules.contains_key(module_name));
+}
+
 /// # Make Package
 /// Given a directory that includes a bsconfig file, read it, and recursively find all other
-/// bsconfig files, and turn those into Packages as well.
-fn build_package<'a>(
-    map: &'a mut AHashMap<String, Package>,
+/// bsconfig files, and turn those into Packages as well. The configs that can't be read are kept
+/// in `config_errors` by the directory of the package, and the rest of the tree is still read, so
+/// all of them are reported at once by `get_package_tree_errors`.
+fn build_package(
+    map: &mut AHashMap<String, Package>,
+    config_warnings: &mut AHashMap<String, Vec<String>>,
+    config_errors: &mut AHashMap<String, String>,
     bsconfig: bsconfig::T,
     package_dir: &str,
-    project_root: &str,
     is_pinned_dep: bool,
     is_root: bool,
-) -> &'a mut AHashMap<String, Package> {
-    // let (package_dir, bsconfig) = read_bsconfig(package_name, project_root, is_root);
+) {
     let copied_bsconfig = bsconfig.to_owned();
 
     /* At this point in time we may have started encountering elements multiple times as there is
@@ -260,30 +396,22 @@ fn build_package<'a>(
             },
             modules: None,
             package_dir: package_dir.to_string(),
+            build_dir: package_dir.to_string(),
             dirs: None,
             is_pinned_dep: is_pinned_dep,
             is_root,
         }
     });
 
-    bsconfig
+    let children = bsconfig
         .bs_dependencies
         .to_owned()
         .unwrap_or(vec![])
         .iter()
         .filter_map(|package_name| {
-            let package_dir = match PathBuf::from(get_package_dir(package_name, false)).canonicalize() {
-                Ok(dir) => dir.to_string_lossy().to_string(),
-                Err(e) => {
-                    print!(
-                        "{} {} Error building package tree (are node_modules up-to-date?)... \n More details: {}",
-                        style("[1/2]").bold().dim(),
-                        CROSS,
-                        e.to_string()
-                    );
-                    std::process::exit(2)
-                }
-            };
+            // packages that can't be found are reported by `get_package_tree_errors`, together
+            // with the other problems in the package tree
+            let package_dir = resolve_package_dir(package_name, package_dir)?;
 
             if !map.contains_key(package_name) {
                 Some(package_dir)
@@ -294,23 +422,33 @@ fn build_package<'a>(
         .collect::<Vec<String>>()
         // read all bsconfig files simultanously instead of blocking
         .par_iter()
-        .map(|package_dir| (package_dir.to_owned(), read_bsconfig(package_dir)))
-        .collect::<Vec<(String, bsconfig::T)>>()
-        .iter()
-        .fold(map, |map, (package_dir, child_bsconfig)| {
-            build_package(
-                map,
-                child_bsconfig.to_owned(),
-                &package_dir,
-                &project_root,
-                bsconfig
+        .map(|package_dir| (package_dir.to_owned(), read_bsconfig_with_warnings(package_dir)))
+        .collect::<Vec<(String, Result<(bsconfig::T, Vec<String>), String>)>>();
+
+    for (child_package_dir, child) in children {
+        match child {
+            Ok((child_bsconfig, warnings)) => {
+                config_warnings.insert(child_package_dir.to_owned(), warnings);
+                let is_pinned_dep = bsconfig
                     .pinned_dependencies
                     .as_ref()
                     .map(|p| p.contains(&child_bsconfig.name))
-                    .unwrap_or(false),
-                false,
-            )
-        })
+                    .unwrap_or(false);
+                build_package(
+                    map,
+                    config_warnings,
+                    config_errors,
+                    child_bsconfig,
+                    &child_package_dir,
+                    is_pinned_dep,
+                    false,
+                );
+            }
+            Err(error) => {
+                config_errors.insert(child_package_dir, error);
+            }
+        }
+    }
 }
 
 /// `get_source_files` is essentially a wrapper around `read_structure`, which read a
@@ -365,19 +503,31 @@ pub fn namespace_from_package_name(package_name: &str) -> String {
 
 /// This takes the tree of packages, and finds all the source files for each, adding them to the
 /// respective packages.
+/// The source files of prebuilt dependencies are read from their manifest instead.
 fn extend_with_children(
     filter: &Option<regex::Regex>,
     mut build: AHashMap<String, Package>,
 ) -> AHashMap<String, Package> {
     for (_key, value) in build.iter_mut() {
-        let mut map: AHashMap<String, SourceFileMeta> = AHashMap::new();
-        value
-            .source_folders
-            .par_iter()
-            .map(|source| get_source_files(Path::new(&value.package_dir), &filter, source))
-            .collect::<Vec<AHashMap<String, SourceFileMeta>>>()
-            .into_iter()
-            .for_each(|source| map.extend(source));
+        let prebuilt_source_files = if value.is_prebuilt_dependency() {
+            read_prebuilt_manifest(value, filter)
+        } else {
+            None
+        };
+        let map = match prebuilt_source_files {
+            Some(source_files) => source_files,
+            None => {
+                let mut map: AHashMap<String, SourceFileMeta> = AHashMap::new();
+                value
+                    .source_folders
+                    .par_iter()
+                    .map(|source| get_source_files(Path::new(&value.package_dir), &filter, source))
+                    .collect::<Vec<AHashMap<String, SourceFileMeta>>>()
+                    .into_iter()
+                    .for_each(|source| map.extend(source));
+                map
+            }
+        };
 
         let mut modules = AHashSet::from_iter(
             map.keys()
@@ -404,20 +554,221 @@ fn extend_with_children(
     build
 }
 
+/// Identifies the installed version of a package by the contents of its package.json and config.
+/// When a dependency is updated, this changes, even when the files keep their old modification
+/// times (npm sets those to a fixed date).
+pub fn get_package_identity(package: &Package) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for file in ["package.json", "rescript.json", "bsconfig.json"] {
+        if let Ok(contents) = fs::read(Path::new(&package.package_dir).join(file)) {
+            hasher.update(file.as_bytes());
+            hasher.update(&contents);
+        }
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+/// The size and modification time of a source file of a prebuilt dependency, as it is kept in the
+/// manifest of the dependency.
+fn get_source_file_stamp(package: &Package, path: &str) -> Option<String> {
+    let metadata = fs::metadata(Path::new(&package.package_dir).join(path)).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+    Some(format!("{}\t{}", metadata.len(), modified.as_nanos()))
+}
+
+/// The source files of a prebuilt dependency, as they were when it was built completely. They
+/// get the oldest possible modification time, so the modules are only parsed and compiled again
+/// when their artifacts are missing. Only the listed files are checked, not the source folders.
+/// Returns `None` when the package was updated since, or when one of the files changed in place
+/// (like with patch-package or `npm link`), so the source folders are read again.
+fn read_prebuilt_manifest(
+    package: &Package,
+    filter: &Option<regex::Regex>,
+) -> Option<AHashMap<String, SourceFileMeta>> {
+    let manifest = fs::read_to_string(helpers::get_prebuilt_manifest_path(&package.build_dir)).ok()?;
+    let mut lines = manifest.lines();
+    if lines.next()? != get_package_identity(package) {
+        return None;
+    }
+    let paths = lines
+        .map(|line| {
+            let (stamp, path) = line.rsplit_once('\t')?;
+            (get_source_file_stamp(package, path)? == stamp).then_some(path)
+        })
+        .collect::<Option<Vec<&str>>>()?;
+    Some(
+        paths
+            .into_iter()
+            .filter(|path| {
+                let name = Path::new(path).file_name().and_then(|name| name.to_str()).unwrap_or(path);
+                matches_filter(filter, name)
+            })
+            .map(|path| {
+                (
+                    path.to_string(),
+                    SourceFileMeta {
+                        modified: SystemTime::UNIX_EPOCH,
+                    },
+                )
+            })
+            .collect(),
+    )
+}
+
+/// The directories of the prebuilt dependencies. The watcher ignores the changes in these, because
+/// they are not developed in this project. A change in place is picked up by the next build.
+pub fn get_prebuilt_dependency_dirs(packages: &AHashMap<String, Package>) -> Vec<String> {
+    packages
+        .values()
+        .filter(|package| package.is_prebuilt_dependency())
+        .map(|package| package.package_dir.to_owned())
+        .collect()
+}
+
+/// Keeps the identity and the source files (with their size and modification time) of the prebuilt
+/// dependencies, so the next builds don't have to scan their source folders. This should only be
+/// called when all modules were compiled, and the source files were not filtered. In a focused
+/// build, only the packages that were built get a manifest.
+pub fn write_prebuilt_manifests(build_state: &BuildState) {
+    build_state
+        .get_built_packages()
+        .into_iter()
+        .filter(|package| package.is_prebuilt_dependency())
+        .for_each(|package| {
+            if let Some(source_files) = &package.source_files {
+                let mut paths = source_files.keys().collect::<Vec<&String>>();
+                paths.sort();
+                let lines = paths
+                    .into_iter()
+                    .map(|path| {
+                        get_source_file_stamp(package, path).map(|stamp| format!("{}\t{}", stamp, path))
+                    })
+                    .collect::<Option<Vec<String>>>();
+                if let Some(lines) = lines {
+                    let manifest = [vec![get_package_identity(package)], lines].concat().join("\n");
+                    let manifest_path = helpers::get_prebuilt_manifest_path(&package.build_dir);
+                    if fs::read_to_string(&manifest_path).ok().as_ref() != Some(&manifest) {
+                        let _ = fs::write(manifest_path, manifest);
+                    }
+                }
+            }
+        });
+}
+
+/// Finds the problems in the package tree that would otherwise only show up later as confusing
+/// compile errors: configs that can't be read, dependencies that can't be found, dependencies of
+/// which the name in the config doesn't match the directory, pinned dependencies that are not a
+/// dependency, and cycles between packages. All of them are collected, so they can be fixed at once.
+pub fn get_package_tree_errors(
+    packages: &AHashMap<String, Package>,
+    config_errors: &AHashMap<String, String>,
+) -> Vec<String> {
+    let mut package_names = packages.keys().collect::<Vec<&String>>();
+    package_names.sort();
+
+    let mut config_error_dirs = config_errors.keys().collect::<Vec<&String>>();
+    config_error_dirs.sort();
+    let mut errors = config_error_dirs
+        .into_iter()
+        .map(|package_dir| config_errors[package_dir].to_owned())
+        .collect::<Vec<String>>();
+    for package_name in package_names.iter() {
+        let package = &packages[*package_name];
+        let dependencies = package.bsconfig.bs_dependencies.to_owned().unwrap_or_default();
+
+        for pinned_dependency in package.bsconfig.pinned_dependencies.iter().flatten() {
+            if !dependencies.contains(pinned_dependency) {
+                errors.push(format!(
+                    "{} is listed in the pinned-dependencies of {}, but not in its bs-dependencies",
+                    pinned_dependency, package_name
+                ));
+            }
+        }
+
+        for dependency in dependencies.iter().filter(|dependency| !packages.contains_key(*dependency)) {
+            let package_dir = resolve_package_dir(dependency, &package.package_dir);
+            // the config of the dependency is found, but it can't be read, which is reported above
+            if package_dir
+                .as_ref()
+                .map(|package_dir| config_errors.contains_key(package_dir))
+                .unwrap_or(false)
+            {
+                continue;
+            }
+            let found_package = package_dir.as_ref().and_then(|package_dir| {
+                packages
+                    .values()
+                    .find(|package| &package.package_dir == package_dir)
+            });
+            errors.push(match (package_dir, found_package) {
+                (Some(package_dir), Some(found_package)) => format!(
+                    "{} depends on {}, but the package in {} is named {} in its config. The name \
+                     of a package must match its directory",
+                    package_name, dependency, package_dir, found_package.name
+                ),
+                _ => format!(
+                    "{} depends on {}, but it could not be found in the node_modules of {} or any \
+                     of its parent directories (are node_modules up-to-date?)",
+                    package_name, dependency, package.package_dir
+                ),
+            });
+        }
+    }
+
+    let graph = packages
+        .iter()
+        .map(|(package_name, package)| {
+            let mut dependencies = package.bsconfig.bs_dependencies.to_owned().unwrap_or_default();
+            dependencies.sort();
+            (package_name.to_owned(), dependencies)
+        })
+        .collect::<AHashMap<String, Vec<String>>>();
+    for component in dependency_cycle::strongly_connected_components(&graph) {
+        errors.push(format!(
+            "Found a circular dependency between packages: {}",
+            dependency_cycle::cycle_in_component(&graph, &component).join(" -> ")
+        ));
+    }
+
+    errors
+}
+
 /// Make turns a folder, that should contain a bsconfig, into a tree of Packages.
 /// It does so in two steps:
 /// 1. Get all the packages parsed, and take all the source folders from the bsconfig
 /// 2. Take the (by then deduplicated) packages, and find all the '.re', '.res', '.ml' and
 ///    interface files.
 /// The two step process is there to reduce IO overhead
-pub fn make(filter: &Option<regex::Regex>, root_folder: &str) -> AHashMap<String, Package> {
+pub fn make(filter: &Option<regex::Regex>, root_folder: &str) -> Result<AHashMap<String, Package>, String> {
     /* The build_package get's called recursively. By using extend, we deduplicate all the packages
      * */
     let mut map: AHashMap<String, Package> = AHashMap::new();
 
-    let package_dir = get_package_dir("", true);
-    let bsconfig = read_bsconfig(&package_dir);
-    build_package(&mut map, bsconfig, &package_dir, root_folder, true, true);
+    // the warnings about the configs, by the directory of the package, so every config is only
+    // reported once
+    let mut config_warnings: AHashMap<String, Vec<String>> = AHashMap::new();
+
+    let (bsconfig, warnings) = read_bsconfig_with_warnings(root_folder)?;
+    config_warnings.insert(root_folder.to_owned(), warnings);
+    let mut config_errors: AHashMap<String, String> = AHashMap::new();
+    let build_dir = bsconfig.rewatch.as_ref().and_then(|rewatch| rewatch.build_dir.to_owned());
+    build_package(
+        &mut map,
+        &mut config_warnings,
+        &mut config_errors,
+        bsconfig,
+        root_folder,
+        true,
+        true,
+    );
+    print_config_warnings(&config_warnings);
+    let errors = get_package_tree_errors(&map, &config_errors);
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+    if let Some(build_dir) = build_dir {
+        set_build_dirs(&mut map, root_folder, &build_dir);
+    }
     /* Once we have the deduplicated packages, we can add the source files for each - to minimize
      * the IO */
     let result = extend_with_children(&filter, map);
@@ -427,22 +778,64 @@ pub fn make(filter: &Option<regex::Regex>, root_folder: &str) -> AHashMap<String
         .for_each(|package| match &package.dirs {
             Some(dirs) => dirs.iter().for_each(|dir| {
                 let _ = std::fs::create_dir_all(
-                    std::path::Path::new(&helpers::get_bs_build_path(
-                        root_folder,
-                        &package.name,
-                        package.is_root,
-                    ))
-                    .join(dir),
+                    std::path::Path::new(&helpers::get_bs_build_path(&package.build_dir)).join(dir),
                 );
             }),
             None => (),
         });
     result
+        .values()
+        .filter(|package| package.is_built_out_of_tree())
+        .for_each(prepare_out_of_tree_build_dir);
+    Ok(result)
 }
 
-pub fn get_package_name(path: &str) -> String {
-    let bsconfig = read_bsconfig(&path);
-    bsconfig.name
+fn print_config_warnings(config_warnings: &AHashMap<String, Vec<String>>) {
+    let mut package_dirs = config_warnings.keys().collect::<Vec<&String>>();
+    package_dirs.sort();
+    package_dirs
+        .into_iter()
+        .flat_map(|package_dir| &config_warnings[package_dir])
+        .for_each(|warning| println!("{}\r{}: {}", LINE_CLEAR, style("Warning").yellow(), warning));
+}
+
+/// The packages that are not developed in this project (all but the root and the pinned
+/// dependencies) are built in `<root>/<build-dir>/<package name>`, so nothing is written into
+/// node_modules, which may be read-only.
+fn set_build_dirs(packages: &mut AHashMap<String, Package>, root_folder: &str, build_dir: &str) {
+    packages
+        .values_mut()
+        .filter(|package| package.is_prebuilt_dependency())
+        .for_each(|package| {
+            package.build_dir = Path::new(root_folder)
+                .join(build_dir)
+                .join(&package.name)
+                .to_string_lossy()
+                .to_string();
+        });
+}
+
+/// bsc writes the JavaScript files relative to the nearest directory with a config, looking up from
+/// the directory it runs in. A copy of the config in the build dir makes sure the JavaScript of a
+/// package that is built out of tree ends up in the build dir as well, instead of in the root. The
+/// source folders are mirrored in the build dir, so the JavaScript files can be written there.
+fn prepare_out_of_tree_build_dir(package: &Package) {
+    if let Some(dirs) = &package.dirs {
+        dirs.iter().for_each(|dir| {
+            let _ = fs::create_dir_all(Path::new(&package.build_dir).join(dir));
+        });
+    }
+    for config_file in ["rescript.json", "bsconfig.json"] {
+        let config_path = Path::new(&package.package_dir).join(config_file);
+        if config_path.exists() {
+            let _ = fs::copy(config_path, Path::new(&package.build_dir).join(config_file));
+            return;
+        }
+    }
+}
+
+pub fn get_package_name(path: &str) -> Result<String, String> {
+    read_bsconfig(&path).map(|bsconfig| bsconfig.name)
 }
 
 pub fn parse_packages(build_state: &mut BuildState) {
@@ -456,13 +849,8 @@ pub fn parse_packages(build_state: &mut BuildState) {
                 Some(package_modules) => build_state.module_names.extend(package_modules),
                 None => (),
             }
-            let build_path_abs =
-                helpers::get_build_path(&build_state.project_root, &package.bsconfig.name, package.is_root);
-            let bs_build_path = helpers::get_bs_build_path(
-                &build_state.project_root,
-                &package.bsconfig.name,
-                package.is_root,
-            );
+            let build_path_abs = helpers::get_build_path(&package.build_dir);
+            let bs_build_path = helpers::get_bs_build_path(&package.build_dir);
             helpers::create_build_path(&build_path_abs);
             helpers::create_build_path(&bs_build_path);
 
@@ -493,8 +881,7 @@ pub fn parse_packages(build_state: &mut BuildState) {
                     .filter(|module_name| helpers::is_non_exotic_module_name(module_name))
                     .collect::<AHashSet<String>>();
 
-                let mlmap =
-                    namespaces::gen_mlmap(&package, namespace, &depending_modules, &build_state.project_root);
+                let mlmap = namespaces::gen_mlmap(&package, namespace, &depending_modules);
 
                 // mlmap will be compiled in the AST generation step
                 // compile_mlmap(&package, namespace, &project_root);
@@ -525,6 +912,7 @@ pub fn parse_packages(build_state: &mut BuildState) {
                         package_name: package.name.to_owned(),
                         compile_dirty: false,
                         last_compiled_cmt: None,
+                        last_compiled_cmj: None,
                         last_compiled_cmi: None,
                     },
                 );
@@ -545,15 +933,11 @@ pub fn parse_packages(build_state: &mut BuildState) {
                             .modules
                             .entry(module_name.to_string())
                             .and_modify(|module| match module.source_type {
-                                SourceType::SourceFile(ref mut source_file) => {
-                                    if &source_file.implementation.path != file {
-                                        error!("Duplicate files found for module: {}", &module_name);
-                                        error!("file 1: {}", &source_file.implementation.path);
-                                        error!("file 2: {}", &file);
-
-                                        panic!("Unable to continue... See log output above...");
-                                    }
-                                    source_file.implementation.path = file.to_owned();
+                                // duplicate module names are reported by `get_module_name_collisions`
+                                // before we get here, so we keep the first file we found
+                                SourceType::SourceFile(ref mut source_file)
+                                    if &source_file.implementation.path == file =>
+                                {
                                     source_file.implementation.last_modified = metadata.modified;
                                     source_file.implementation.dirty = true;
                                 }
@@ -575,6 +959,7 @@ pub fn parse_packages(build_state: &mut BuildState) {
                                 package_name: package.name.to_owned(),
                                 compile_dirty: true,
                                 last_compiled_cmt: None,
+                                last_compiled_cmj: None,
                                 last_compiled_cmi: None,
                             });
                     } else {
@@ -627,6 +1012,7 @@ pub fn parse_packages(build_state: &mut BuildState) {
                                         package_name: package.name.to_owned(),
                                         compile_dirty: true,
                                         last_compiled_cmt: None,
+                                        last_compiled_cmj: None,
                                         last_compiled_cmi: None,
                                     });
                             }
@@ -637,18 +1023,163 @@ pub fn parse_packages(build_state: &mut BuildState) {
         });
 }
 
-fn check_if_rescript11_or_higher(version: &str) -> bool {
-    version.split(".").nth(0).unwrap().parse::<usize>().unwrap() >= 11
+/// A file or namespace that produces a module with the given name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleNameClaim {
+    pub module_name: String,
+    pub package_name: String,
+    pub location: String,
+}
+
+/// Finds all the module names that are produced by more than one file or namespace, across all
+/// packages. Module names are compared case-insensitively, because the compiled assets of `Foo`
+/// and `FOO` end up in the same file on case-insensitive file systems. Every collision contains
+/// all the claims on that name, so we can report them at once.
+pub fn get_module_name_collisions(packages: &AHashMap<String, Package>) -> Vec<Vec<ModuleNameClaim>> {
+    let mut claims: AHashMap<String, Vec<ModuleNameClaim>> = AHashMap::new();
+
+    for package in packages.values() {
+        let mut add_claim = |module_name: String, location: String| {
+            claims
+                .entry(module_name.to_lowercase())
+                .or_insert(vec![])
+                .push(ModuleNameClaim {
+                    module_name,
+                    package_name: package.name.to_owned(),
+                    location,
+                })
+        };
+
+        if let Some(namespace) = package.namespace.to_suffix() {
+            add_claim(namespace.to_owned(), format!("namespace {}", namespace));
+        }
+
+        // interfaces belong to the module of their implementation, so only those claim a name
+        package
+            .source_files
+            .iter()
+            .flat_map(|source_files| source_files.keys())
+            .filter(|file| {
+                PathBuf::from(file)
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .map(helpers::is_implementation_file)
+                    .unwrap_or(false)
+            })
+            .for_each(|file| {
+                add_claim(
+                    helpers::file_path_to_module_name(file, &package.namespace),
+                    file.to_owned(),
+                )
+            });
+    }
+
+    let mut collisions = claims
+        .into_values()
+        .filter(|claims| claims.len() > 1)
+        .map(|mut claims| {
+            claims.sort_by(|a, b| {
+                (&a.package_name, &a.location).cmp(&(&b.package_name, &b.location))
+            });
+            claims
+        })
+        .collect::<Vec<Vec<ModuleNameClaim>>>();
+    collisions.sort_by(|a, b| a[0].module_name.cmp(&b[0].module_name));
+    collisions
+}
+
+pub fn format_module_name_collisions(collisions: &[Vec<ModuleNameClaim>]) -> String {
+    format!(
+        "\n{}\n{}\n\n{}\n",
+        style("Can't continue... Found duplicate module names:").red(),
+        collisions
+            .iter()
+            .map(|claims| {
+                claims
+                    .iter()
+                    .map(|claim| {
+                        format!(
+                            "  {} in {} ({})",
+                            helpers::format_namespaced_module_name(&claim.module_name),
+                            claim.package_name,
+                            claim.location
+                        )
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            })
+            .collect::<Vec<String>>()
+            .join("\n\n"),
+        "Module names must be unique, also when ignoring case. Rename one of the files, or configure a \
+         namespace for the package."
+    )
 }
 
 impl Package {
-    pub fn get_jsx_args(&self) -> Vec<String> {
-        match (self.bsconfig.reason.to_owned(), self.bsconfig.jsx.to_owned()) {
+    /// The JSX settings that apply to this package. The package's own jsx config takes precedence.
+    /// Otherwise, packages listed in `jsx.v3-dependencies` of the root are compiled with JSX version
+    /// 3, and all other packages use the jsx config of the root.
+    pub fn get_jsx_config(&self, root_package: &Package) -> Option<bsconfig::JsxSpecs> {
+        let root_jsx = root_package.bsconfig.jsx.to_owned();
+        let is_v3_dependency = root_jsx
+            .as_ref()
+            .and_then(|jsx| jsx.v3_dependencies.as_ref())
+            .map(|v3_dependencies| v3_dependencies.contains(&self.name))
+            .unwrap_or(false);
+
+        match self.bsconfig.jsx.to_owned() {
+            Some(jsx) => Some(jsx),
+            None if is_v3_dependency => Some(bsconfig::JsxSpecs {
+                version: Some(3),
+                module: None,
+                mode: None,
+                v3_dependencies: None,
+                preserve: None,
+            }),
+            None => root_jsx,
+        }
+    }
+
+    /// Checks that the JSX settings of this package are supported by the compiler, so we can
+    /// report a config error before we start building.
+    pub fn validate_jsx(&self, version: &Version, root_package: &Package) -> Result<(), String> {
+        let jsx = match self.get_jsx_config(root_package) {
+            Some(jsx) => jsx,
+            None => return Ok(()),
+        };
+        let error = |message: String| Err(format!("Invalid jsx config in {}: {}", self.name, message));
+
+        match (jsx.version, &jsx.module, jsx.preserve) {
+            (Some(jsx_version), _, _) if jsx_version != 3 && jsx_version != 4 => error(format!(
+                "JSX version {} is not supported, use version 3 or 4",
+                jsx_version
+            )),
+            (Some(3), Some(bsconfig::JsxModule::Other(module)), _) => error(format!(
+                "the JSX module `{}` needs JSX version 4, only `react` is supported with version 3",
+                module
+            )),
+            (Some(3), _, Some(true)) => error("preserve mode needs JSX version 4".to_string()),
+            (_, Some(bsconfig::JsxModule::Other(_)), _)
+                if !version.supports(Capability::GenericJsxModule) =>
+            {
+                error(Capability::GenericJsxModule.unsupported_error(version))
+            }
+            (_, _, Some(true)) if !version.supports(Capability::JsxPreserve) => {
+                error(Capability::JsxPreserve.unsupported_error(version))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn get_jsx_args(&self, root_package: &Package) -> Vec<String> {
+        let reason = self
+            .bsconfig
+            .reason
+            .to_owned()
+            .or(root_package.bsconfig.reason.to_owned());
+        match (reason, self.get_jsx_config(root_package)) {
             (_, Some(jsx)) => match jsx.version {
-                Some(version) if version == 3 || version == 4 => {
-                    vec!["-bs-jsx".to_string(), version.to_string()]
-                }
-                Some(_version) => panic!("Unsupported JSX version"),
+                Some(version) => vec!["-bs-jsx".to_string(), version.to_string()],
                 None => vec![],
             },
             (Some(reason), None) => {
@@ -658,8 +1189,8 @@ impl Package {
         }
     }
 
-    pub fn get_jsx_mode_args(&self) -> Vec<String> {
-        match self.bsconfig.jsx.to_owned() {
+    pub fn get_jsx_mode_args(&self, root_package: &Package) -> Vec<String> {
+        match self.get_jsx_config(root_package) {
             Some(jsx) => match jsx.mode {
                 Some(bsconfig::JsxMode::Classic) => {
                     vec!["-bs-jsx-mode".to_string(), "classic".to_string()]
@@ -674,23 +1205,64 @@ impl Package {
         }
     }
 
-    pub fn get_jsx_module_args(&self) -> Vec<String> {
-        match self.bsconfig.jsx.to_owned() {
+    pub fn get_jsx_module_args(&self, root_package: &Package) -> Vec<String> {
+        match self.get_jsx_config(root_package) {
             Some(jsx) => match jsx.module {
-                Some(bsconfig::JsxModule::React) => {
-                    vec!["-bs-jsx-module".to_string(), "react".to_string()]
-                }
+                Some(module) => vec!["-bs-jsx-module".to_string(), module.to_string()],
                 None => vec![],
             },
             _ => vec![],
         }
     }
 
-    pub fn get_uncurried_args(&self, version: &str, root_package: &packages::Package) -> Vec<String> {
-        if check_if_rescript11_or_higher(version) {
+    pub fn get_jsx_preserve_args(&self, root_package: &Package) -> Vec<String> {
+        match self.get_jsx_config(root_package) {
+            Some(bsconfig::JsxSpecs {
+                preserve: Some(true), ..
+            }) => vec!["-bs-jsx-preserve".to_string()],
+            _ => vec![],
+        }
+    }
+
+    /// The dependencies that are not developed in this project, so all but the root and the pinned
+    /// dependencies. These don't change once they are built, see `read_prebuilt_manifest`.
+    pub fn is_prebuilt_dependency(&self) -> bool {
+        !self.is_root && !self.is_pinned_dep
+    }
+
+    /// Whether the build artifacts (and the JavaScript) of the package are written to a build dir in
+    /// the root, instead of to the package itself.
+    pub fn is_built_out_of_tree(&self) -> bool {
+        self.build_dir != self.package_dir
+    }
+
+    /// The suffix of the generated JavaScript files. This is configured in the root package.
+    pub fn get_suffix(&self) -> bsconfig::Suffix {
+        self.bsconfig.suffix.to_owned().unwrap_or_default()
+    }
+
+    /// Checks that the compiler supports the configured suffix.
+    pub fn validate_suffix(&self, version: &Version) -> Result<(), String> {
+        let suffix = self.get_suffix();
+        if suffix.is_classic() || version.supports(Capability::CustomSuffix) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Invalid suffix in {}: {} `{}`",
+                self.name,
+                Capability::CustomSuffix.unsupported_error(version),
+                suffix
+            ))
+        }
+    }
+
+    /// The package's own `uncurried` setting takes precedence over the one of the root. From
+    /// ReScript 11 on, uncurried mode is the default.
+    pub fn get_uncurried_args(&self, version: &Version, root_package: &packages::Package) -> Vec<String> {
+        if version.supports(Capability::UncurriedByDefault) {
             match (
-                root_package.bsconfig.uncurried.to_owned(),
                 self.bsconfig.uncurried.to_owned(),
+                root_package.bsconfig.uncurried.to_owned(),
             ) {
                 (Some(x), _) | (None, Some(x)) => {
                     if x {
@@ -802,10 +1374,16 @@ pub fn validate_packages_dependencies(packages: &AHashMap<String, Package>) -> b
 
 #[cfg(test)]
 mod test {
-    use crate::bsconfig::Source;
+    use crate::bsconfig::{JsxModule, Source, Suffix};
+    use crate::build::build_types::*;
+    use crate::build::version::Version;
     use ahash::{AHashMap, AHashSet};
 
-    use super::{Package, Namespace};
+    use super::{Namespace, Package, SourceFileMeta};
+
+    fn version(version: &str) -> Version {
+        Version::parse(version).unwrap()
+    }
 
     fn create_package(
         name: String,
@@ -825,6 +1403,9 @@ mod test {
                 pinned_dependencies: Some(pinned_deps),
                 bs_dependencies: Some(bs_deps),
                 bs_dev_dependencies: Some(dev_deps),
+                dependencies: None,
+                dev_dependencies: None,
+                compiler_flags: None,
                 ppx_flags: None,
                 bsc_flags: None,
                 reason: None,
@@ -833,12 +1414,14 @@ mod test {
                 uncurried: None,
                 namespace_entry: None,
                 allowed_dependents,
+                rewatch: None,
             },
             source_folders: AHashSet::new(),
             source_files: None,
             namespace: Namespace::Namespace(String::from("Package1")),
             modules: None,
             package_dir: String::from("./something"),
+            build_dir: String::from("./something"),
             dirs: None,
             is_pinned_dep: false,
             is_root: false,
@@ -958,4 +1541,448 @@ mod test {
         let is_valid = super::validate_packages_dependencies(&packages);
         assert_eq!(is_valid, true)
     }
+
+    fn create_package_with_files(name: &str, namespace: Namespace, files: &[&str]) -> Package {
+        let mut package = create_package(name.to_string(), vec![], vec![], vec![], None);
+        package.namespace = namespace;
+        package.source_files = Some(
+            files
+                .iter()
+                .map(|file| {
+                    (
+                        file.to_string(),
+                        SourceFileMeta {
+                            modified: std::time::SystemTime::UNIX_EPOCH,
+                        },
+                    )
+                })
+                .collect(),
+        );
+        package
+    }
+
+    fn collided_names(packages: Vec<Package>) -> Vec<Vec<(String, String)>> {
+        let packages = packages
+            .into_iter()
+            .map(|package| (package.name.to_owned(), package))
+            .collect::<AHashMap<String, Package>>();
+        super::get_module_name_collisions(&packages)
+            .into_iter()
+            .map(|claims| {
+                claims
+                    .into_iter()
+                    .map(|claim| (claim.module_name, claim.package_name))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_module_name_collisions_across_packages_without_namespace() {
+        let collisions = collided_names(vec![
+            create_package_with_files("Package1", Namespace::NoNamespace, &["src/Foo.res", "src/Foo.resi"]),
+            create_package_with_files("Package2", Namespace::NoNamespace, &["src/Foo.res", "src/Bar.res"]),
+        ]);
+        assert_eq!(
+            collisions,
+            vec![vec![
+                ("Foo".to_string(), "Package1".to_string()),
+                ("Foo".to_string(), "Package2".to_string())
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_module_name_collisions_ignore_case() {
+        let collisions = collided_names(vec![create_package_with_files(
+            "Package1",
+            Namespace::NoNamespace,
+            &["src/Foo.res", "src/nested/FOO.res"],
+        )]);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].len(), 2);
+    }
+
+    #[test]
+    fn test_module_name_collisions_with_namespaces() {
+        let collisions = collided_names(vec![
+            create_package_with_files(
+                "Package1",
+                Namespace::Namespace("Package1".to_string()),
+                &["src/Foo.res"],
+            ),
+            create_package_with_files(
+                "Package2",
+                Namespace::NoNamespace,
+                &["src/Foo.res", "src/Package1.res"],
+            ),
+        ]);
+        assert_eq!(
+            collisions,
+            vec![vec![
+                ("Package1".to_string(), "Package1".to_string()),
+                ("Package1".to_string(), "Package2".to_string())
+            ]]
+        );
+    }
+
+    fn create_package_with_jsx(jsx: crate::bsconfig::JsxSpecs) -> Package {
+        let mut package = create_package(String::from("Package1"), vec![], vec![], vec![], None);
+        package.bsconfig.jsx = Some(jsx);
+        package
+    }
+
+    fn jsx(
+        version: Option<i32>,
+        module: Option<JsxModule>,
+        preserve: Option<bool>,
+    ) -> crate::bsconfig::JsxSpecs {
+        crate::bsconfig::JsxSpecs {
+            version,
+            module,
+            mode: None,
+            v3_dependencies: None,
+            preserve,
+        }
+    }
+
+    #[test]
+    fn test_jsx_args_with_generic_module_and_preserve() {
+        let package = create_package_with_jsx(jsx(
+            Some(4),
+            Some(JsxModule::Other(String::from("Preact"))),
+            Some(true),
+        ));
+        assert_eq!(package.validate_jsx(&version("12.0.0"), &package), Ok(()));
+        assert_eq!(package.get_jsx_module_args(&package), vec!["-bs-jsx-module", "Preact"]);
+        assert_eq!(package.get_jsx_preserve_args(&package), vec!["-bs-jsx-preserve"]);
+    }
+
+    #[test]
+    fn test_validate_jsx_rejects_unsupported_combinations() {
+        let validate = |jsx, rescript_version| {
+            let package = create_package_with_jsx(jsx);
+            package.validate_jsx(&version(rescript_version), &package)
+        };
+        assert!(validate(jsx(Some(5), None, None), "11.0.0").is_err());
+        assert!(validate(
+            jsx(Some(3), Some(JsxModule::Other(String::from("Preact"))), None),
+            "11.0.0"
+        )
+        .is_err());
+        assert!(validate(jsx(Some(4), None, Some(true)), "11.1.0").is_err());
+        assert!(validate(jsx(Some(4), Some(JsxModule::React), None), "11.1.0").is_ok());
+        assert!(validate(
+            jsx(Some(4), Some(JsxModule::Other(String::from("Preact"))), None),
+            "11.0.0-rc.4"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_validate_suffix() {
+        let mut package = create_package(String::from("Package1"), vec![], vec![], vec![], None);
+        package.bsconfig.suffix = Some(Suffix::new(".res.mjs").unwrap());
+        assert!(package.validate_suffix(&version("10.1.4")).is_err());
+        assert!(package.validate_suffix(&version("11.0.0")).is_ok());
+        package.bsconfig.suffix = Some(Suffix::new(".bs.js").unwrap());
+        assert!(package.validate_suffix(&version("10.1.4")).is_ok());
+    }
+
+    #[test]
+    fn test_jsx_and_uncurried_settings_are_resolved_per_package() {
+        let mut root = create_package_with_jsx(jsx(Some(4), Some(JsxModule::React), None));
+        root.name = String::from("Root");
+        root.bsconfig.uncurried = Some(true);
+        root.bsconfig.jsx.as_mut().unwrap().v3_dependencies = Some(vec![String::from("Legacy")]);
+
+        let mut own = create_package_with_jsx(jsx(
+            Some(4),
+            Some(JsxModule::Other(String::from("Preact"))),
+            None,
+        ));
+        own.bsconfig.uncurried = Some(false);
+        let legacy = create_package(String::from("Legacy"), vec![], vec![], vec![], None);
+        let other = create_package(String::from("Other"), vec![], vec![], vec![], None);
+
+        assert_eq!(own.get_jsx_module_args(&root), vec!["-bs-jsx-module", "Preact"]);
+        assert_eq!(own.get_uncurried_args(&version("11.0.0"), &root), Vec::<String>::new());
+        assert_eq!(legacy.get_jsx_args(&root), vec!["-bs-jsx", "3"]);
+        assert_eq!(legacy.get_jsx_module_args(&root), Vec::<String>::new());
+        assert_eq!(other.get_jsx_args(&root), vec!["-bs-jsx", "4"]);
+        assert_eq!(other.get_jsx_module_args(&root), vec!["-bs-jsx-module", "react"]);
+        assert_eq!(other.get_uncurried_args(&version("11.0.0"), &root), vec!["-uncurried"]);
+    }
+
+    fn package_tree(packages: Vec<Package>) -> AHashMap<String, Package> {
+        packages
+            .into_iter()
+            .map(|package| (package.name.to_owned(), package))
+            .collect()
+    }
+
+    /// A build of the packages, in which only the given packages have modules (like in a focused
+    /// build).
+    fn build_state(packages: Vec<Package>, built_packages: &[&str]) -> BuildState {
+        let mut build_state = BuildState::new(
+            String::from("/project"),
+            String::from("Root"),
+            package_tree(packages),
+            crate::build::compiler::Compiler::default(),
+        );
+        for package_name in built_packages {
+            build_state.insert_module(
+                package_name,
+                Module {
+                    source_type: SourceType::MlMap(MlMap { dirty: false }),
+                    deps: AHashSet::new(),
+                    dependents: AHashSet::new(),
+                    package_name: package_name.to_string(),
+                    compile_dirty: false,
+                    last_compiled_cmi: None,
+                    last_compiled_cmt: None,
+                    last_compiled_cmj: None,
+                },
+            );
+        }
+        build_state
+    }
+
+    #[test]
+    fn test_package_tree_errors_are_collected() {
+        let packages = package_tree(vec![
+            create_package(
+                String::from("Root"),
+                vec![String::from("A"), String::from("Not-Installed")],
+                vec![String::from("B")],
+                vec![],
+                None,
+            ),
+            create_package(String::from("A"), vec![String::from("B")], vec![], vec![], None),
+            create_package(String::from("B"), vec![String::from("A")], vec![], vec![], None),
+        ]);
+
+        assert_eq!(
+            super::get_package_tree_errors(&packages, &AHashMap::new()),
+            vec![
+                "B is listed in the pinned-dependencies of Root, but not in its bs-dependencies",
+                "Root depends on Not-Installed, but it could not be found in the node_modules of \
+                 ./something or any of its parent directories (are node_modules up-to-date?)",
+                "Found a circular dependency between packages: A -> B -> A",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_package_tree_without_errors() {
+        let packages = package_tree(vec![
+            create_package(
+                String::from("Root"),
+                vec![String::from("A"), String::from("B")],
+                vec![String::from("B")],
+                vec![],
+                None,
+            ),
+            create_package(String::from("A"), vec![String::from("B")], vec![], vec![], None),
+            create_package(String::from("B"), vec![], vec![], vec![], None),
+        ]);
+
+        assert!(super::get_package_tree_errors(&packages, &AHashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_config_errors_of_all_dependencies_are_reported() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        let write_bsconfig = |dir: &std::path::Path, contents: &str| {
+            std::fs::create_dir_all(dir.join("src")).unwrap();
+            std::fs::write(dir.join("bsconfig.json"), contents).unwrap();
+        };
+        write_bsconfig(
+            &root,
+            r#"{"name": "root", "sources": "src", "bs-dependencies": ["a", "b", "c"]}"#,
+        );
+        write_bsconfig(&root.join("node_modules/a"), r#"{"name": "a", "sources": 1}"#);
+        write_bsconfig(&root.join("node_modules/b"), r#"{"name": "b""#);
+        // the dependencies of a package that can be read are still read
+        write_bsconfig(
+            &root.join("node_modules/c"),
+            r#"{"name": "c", "sources": "src", "bs-dependencies": ["d"]}"#,
+        );
+        write_bsconfig(&root.join("node_modules/d"), r#"{"sources": "src"}"#);
+
+        let error = super::make(&None, &root.to_string_lossy()).err().unwrap();
+        let errors = error.lines().collect::<Vec<&str>>();
+        assert_eq!(errors.len(), 3, "{}", error);
+        for (index, package_name) in ["a", "b", "d"].iter().enumerate() {
+            let config_path = root.join("node_modules").join(package_name).join("bsconfig.json");
+            assert!(errors[index].contains(&*config_path.to_string_lossy()), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_resolve_package_dir_walks_up_and_skips_node_modules_folders() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().to_path_buf();
+        let hoisted = root.join("node_modules/hoisted");
+        let nested = root.join("node_modules/dependent/node_modules/nested");
+        let sibling = root.join("node_modules/.pnpm/pkg/node_modules/sibling");
+        let pnpm_package = root.join("node_modules/.pnpm/pkg/node_modules/pkg");
+        for dir in [&hoisted, &nested, &sibling, &pnpm_package] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        let dependent = root.join("node_modules/dependent");
+        let resolve = |name: &str, from: &std::path::Path| {
+            super::resolve_package_dir(name, &from.to_string_lossy())
+                .map(std::path::PathBuf::from)
+        };
+
+        assert_eq!(resolve("nested", &dependent), Some(nested.canonicalize().unwrap()));
+        assert_eq!(resolve("hoisted", &dependent), Some(hoisted.canonicalize().unwrap()));
+        assert_eq!(resolve("sibling", &pnpm_package), Some(sibling.canonicalize().unwrap()));
+        assert_eq!(resolve("nested", &root), None);
+    }
+
+    #[test]
+    fn test_find_project_root_walks_up_to_the_root_that_depends_on_the_package() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().to_path_buf();
+        let write_bsconfig = |dir: &std::path::Path, name: &str, dependencies: &str| {
+            std::fs::create_dir_all(dir.join("src")).unwrap();
+            std::fs::write(
+                dir.join("bsconfig.json"),
+                format!(
+                    r#"{{"name": "{}", "sources": "src", "bs-dependencies": [{}]}}"#,
+                    name, dependencies
+                ),
+            )
+            .unwrap();
+        };
+        write_bsconfig(&root, "root", r#""app""#);
+        write_bsconfig(&root.join("packages/app"), "app", "");
+        write_bsconfig(&root.join("packages/ui"), "ui", "");
+        write_bsconfig(&root.join("other"), "other", "");
+        std::fs::write(root.join("package.json"), r#"{"workspaces": ["packages/*"]}"#).unwrap();
+        let root_dir = root.canonicalize().unwrap().to_string_lossy().to_string();
+        let find = |dir: &str| super::find_project_root(&root.join(dir).to_string_lossy()).unwrap();
+
+        assert_eq!(find("."), (root_dir.to_owned(), None));
+        assert_eq!(find("packages/app/src"), (root_dir.to_owned(), Some("app".to_string())));
+        assert_eq!(find("packages/ui"), (root_dir.to_owned(), Some("ui".to_string())));
+        assert_eq!(
+            find("other"),
+            (root.join("other").canonicalize().unwrap().to_string_lossy().to_string(), None)
+        );
+    }
+
+    #[test]
+    fn test_only_dependencies_that_are_not_developed_in_the_project_are_built_out_of_tree() {
+        let mut root = create_package(String::from("Root"), vec![], vec![], vec![], None);
+        root.is_root = true;
+        let mut pinned = create_package(String::from("Pinned"), vec![], vec![], vec![], None);
+        pinned.is_pinned_dep = true;
+        let dependency = create_package(String::from("@org/dependency"), vec![], vec![], vec![], None);
+        let mut packages = package_tree(vec![root, pinned, dependency]);
+
+        super::set_build_dirs(&mut packages, "/project", "lib/rewatch");
+
+        assert!(!packages["Root"].is_built_out_of_tree());
+        assert!(!packages["Pinned"].is_built_out_of_tree());
+        assert_eq!(
+            packages["@org/dependency"].build_dir,
+            "/project/lib/rewatch/@org/dependency"
+        );
+        assert_eq!(
+            crate::helpers::get_build_path(&packages["@org/dependency"].build_dir),
+            "/project/lib/rewatch/@org/dependency/lib/ocaml"
+        );
+    }
+
+    #[test]
+    fn test_prebuilt_manifest_is_read_until_the_dependency_is_updated() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let package_dir = temp_dir.path().to_path_buf();
+        std::fs::create_dir_all(package_dir.join("lib/bs")).unwrap();
+        std::fs::create_dir_all(package_dir.join("src")).unwrap();
+        std::fs::write(package_dir.join("package.json"), r#"{"version": "1.0.0"}"#).unwrap();
+        std::fs::write(package_dir.join("src/A.res"), "let a = 1").unwrap();
+        std::fs::write(package_dir.join("src/A_test.res"), "let test = A.a").unwrap();
+        let mut dependency =
+            create_package_with_files("Dependency", Namespace::NoNamespace, &["src/A.res", "src/A_test.res"]);
+        dependency.package_dir = package_dir.to_string_lossy().to_string();
+        dependency.build_dir = dependency.package_dir.to_owned();
+        let mut root = create_package_with_files("Root", Namespace::NoNamespace, &["src/Root.res"]);
+        root.is_root = true;
+        root.package_dir = dependency.package_dir.to_owned();
+        root.build_dir = dependency.package_dir.to_owned();
+
+        // the dependency is not built when the build is focused on a package that doesn't use it
+        super::write_prebuilt_manifests(&build_state(
+            vec![dependency.to_owned(), root.to_owned()],
+            &["Root"],
+        ));
+        assert!(super::read_prebuilt_manifest(&dependency, &None).is_none());
+
+        // the root is not a prebuilt dependency, so only the manifest of the dependency is written
+        super::write_prebuilt_manifests(&build_state(
+            vec![dependency.to_owned(), root],
+            &["Dependency", "Root"],
+        ));
+        let mut source_files = super::read_prebuilt_manifest(&dependency, &None)
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<(String, SourceFileMeta)>>();
+        source_files.sort_by(|(a, _), (b, _)| a.cmp(b));
+        assert_eq!(
+            source_files.iter().map(|(path, _)| path.as_str()).collect::<Vec<&str>>(),
+            vec!["src/A.res", "src/A_test.res"]
+        );
+        assert!(source_files
+            .iter()
+            .all(|(_, meta)| meta.modified == std::time::SystemTime::UNIX_EPOCH));
+
+        let filter = Some(regex::Regex::new("^[^_]*$").unwrap());
+        let filtered = super::read_prebuilt_manifest(&dependency, &filter).unwrap();
+        assert_eq!(filtered.keys().collect::<Vec<&String>>(), vec!["src/A.res"]);
+
+        // a source file that is patched in place is picked up without a new version
+        std::fs::write(package_dir.join("src/A.res"), "let a = 2 // patched").unwrap();
+        assert!(super::read_prebuilt_manifest(&dependency, &None).is_none());
+
+        super::write_prebuilt_manifests(&build_state(vec![dependency.to_owned()], &["Dependency"]));
+        assert!(super::read_prebuilt_manifest(&dependency, &None).is_some());
+        std::fs::write(package_dir.join("package.json"), r#"{"version": "1.1.0"}"#).unwrap();
+        assert!(super::read_prebuilt_manifest(&dependency, &None).is_none());
+    }
+
+    #[test]
+    fn test_focus_packages_are_the_package_and_its_dependencies() {
+        let packages = package_tree(vec![
+            create_package(String::from("Root"), vec![String::from("App")], vec![], vec![], None),
+            create_package(String::from("App"), vec![String::from("Ui")], vec![], vec![], None),
+            create_package(String::from("Ui"), vec![], vec![], vec![], None),
+        ]);
+        let mut focus_packages = super::get_focus_packages(&packages, "App")
+            .into_iter()
+            .collect::<Vec<String>>();
+        focus_packages.sort();
+        assert_eq!(focus_packages, vec![String::from("App"), String::from("Ui")]);
+    }
+
+    #[test]
+    fn test_focus_has_to_be_a_dependency_of_the_root() {
+        let packages = package_tree(vec![
+            create_package(String::from("Root"), vec![String::from("App")], vec![], vec![], None),
+            create_package(String::from("App"), vec![], vec![], vec![], None),
+        ]);
+        assert!(super::validate_focus(&packages, &None, "Root").is_ok());
+        assert!(super::validate_focus(&packages, &Some(String::from("App")), "Root").is_ok());
+        assert_eq!(
+            super::validate_focus(&packages, &Some(String::from("Docs")), "Root"),
+            Err(String::from(
+                "Docs is in the workspace of Root but not one of its dependencies, add \"Docs\" to the \
+                 bs-dependencies of Root to build it"
+            ))
+        );
+    }
 }
@@ -1,18 +1,22 @@
 use super::build_types::*;
+use super::compiler::Compiler;
 use super::logs;
 use super::namespaces;
 use super::packages;
+use super::version::Version;
 use crate::bsconfig;
 use crate::bsconfig::OneOrMore;
 use crate::helpers;
 use log::debug;
 use rayon::prelude::*;
+use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 pub fn generate_asts(
-    version: &str,
+    version: &Version,
     build_state: &mut BuildState,
+    cancelled: &AtomicBool,
     inc: impl Fn() -> () + std::marker::Sync,
 ) -> Result<String, String> {
     let mut has_failure = false;
@@ -28,34 +32,36 @@ pub fn generate_asts(
                 .get_package(&module.package_name)
                 .expect("Package not found");
             match &module.source_type {
-                SourceType::MlMap(_) => {
+                SourceType::MlMap(mlmap) => {
                     // probably better to do this in a different function
                     // specific to compiling mlmaps
                     let path = helpers::get_mlmap_path(
-                        &build_state.project_root,
-                        &module.package_name,
+                        &package.build_dir,
                         &package
                             .namespace
                             .to_suffix()
                             .expect("namespace should be set for mlmap module"),
-                        package.is_root,
                     );
                     let compile_path = helpers::get_mlmap_compile_path(
-                        &build_state.project_root,
-                        &module.package_name,
+                        &package.build_dir,
                         &package
                             .namespace
                             .to_suffix()
                             .expect("namespace should be set for mlmap module"),
-                        package.is_root,
                     );
-                    let mlmap_hash = helpers::compute_file_hash(&compile_path);
-                    namespaces::compile_mlmap(&package, module_name, &build_state.project_root);
-                    let mlmap_hash_after = helpers::compute_file_hash(&compile_path);
+                    // the mlmap is only written when the modules in the namespace changed, so we
+                    // don't have to compile it again when it's older than its compile assets
+                    let is_dirty = if mlmap.dirty || !namespaces::is_mlmap_compiled(&path, &compile_path) {
+                        let mlmap_hash = helpers::compute_file_hash(&compile_path);
+                        namespaces::compile_mlmap(&package, module_name, &build_state.compiler);
+                        let mlmap_hash_after = helpers::compute_file_hash(&compile_path);
 
-                    let is_dirty = match (mlmap_hash, mlmap_hash_after) {
-                        (Some(digest), Some(digest_after)) => !digest.eq(&digest_after),
-                        _ => true,
+                        match (mlmap_hash, mlmap_hash_after) {
+                            (Some(digest), Some(digest_after)) => !digest.eq(&digest_after),
+                            _ => true,
+                        }
+                    } else {
+                        false
                     };
 
                     (module_name.to_owned(), Ok((path, None)), Ok(None), is_dirty)
@@ -64,16 +70,27 @@ pub fn generate_asts(
                 SourceType::SourceFile(source_file) => {
                     let root_package = build_state.get_package(&build_state.root_config_name).unwrap();
 
-                    let (ast_path, iast_path, dirty) = if source_file.implementation.dirty
-                        || source_file.interface.as_ref().map(|i| i.dirty).unwrap_or(false)
+                    // when the build is cancelled, we leave the remaining files dirty, so they are
+                    // parsed in the next build
+                    let (ast_path, iast_path, dirty) = if (source_file.implementation.dirty
+                        || source_file.interface.as_ref().map(|i| i.dirty).unwrap_or(false))
+                        && !cancelled.load(Ordering::Relaxed)
                     {
                         // dbg!("Compiling", source_file.implementation.path.to_owned());
                         inc();
+                        let ast_file_path =
+                            helpers::get_ast_path(&source_file.implementation.path, &package.build_dir);
+                        let iast_file_path = source_file
+                            .interface
+                            .as_ref()
+                            .map(|interface| helpers::get_iast_path(&interface.path, &package.build_dir));
+
                         let ast_result = generate_ast(
                             package.to_owned(),
                             root_package.to_owned(),
                             &source_file.implementation.path.to_owned(),
                             &build_state.project_root,
+                            &build_state.compiler,
                             &version,
                         );
 
@@ -83,13 +100,22 @@ pub fn generate_asts(
                                 root_package.to_owned(),
                                 &interface_file_path.to_owned(),
                                 &build_state.project_root,
+                                &build_state.compiler,
                                 &version,
                             )
                             .map(|result| Some(result)),
                             _ => Ok(None),
                         };
 
-                        (ast_result, iast_result, true)
+                        // when the ASTs are the same as the ones that were compiled, for instance because
+                        // only whitespace or comments changed, the module doesn't have to be compiled again
+                        let is_ast_changed = ast_result.is_err()
+                            || iast_result.is_err()
+                            || !std::iter::once(&ast_file_path)
+                                .chain(iast_file_path.as_ref())
+                                .all(|ast_file_path| is_ast_compiled(ast_file_path));
+
+                        (ast_result, iast_result, is_ast_changed)
                     } else {
                         (
                             Ok((
@@ -137,7 +163,7 @@ pub fn generate_asts(
                                     }
                                     _ => (),
                                 }
-                                logs::append(&build_state.project_root, package.is_root, &package.name, &err);
+                                logs::append(package, &err);
                                 stderr.push_str(&err);
                             }
                         }
@@ -149,7 +175,7 @@ pub fn generate_asts(
                             }
                             _ => (),
                         }
-                        logs::append(&build_state.project_root, package.is_root, &package.name, &err);
+                        logs::append(package, &err);
                         has_failure = true;
                         stderr.push_str(&err);
                     }
@@ -168,7 +194,7 @@ pub fn generate_asts(
                                     }
                                     _ => (),
                                 }
-                                logs::append(&build_state.project_root, package.is_root, &package.name, &err);
+                                logs::append(package, &err);
                                 stderr.push_str(&err);
                             }
                         }
@@ -184,7 +210,7 @@ pub fn generate_asts(
                             }
                             _ => (),
                         }
-                        logs::append(&build_state.project_root, package.is_root, &package.name, &err);
+                        logs::append(package, &err);
                         has_failure = true;
                         stderr.push_str(&err);
                     }
@@ -199,43 +225,87 @@ pub fn generate_asts(
     }
 }
 
+/// Whether the AST is the same as the one the compile assets were compiled from. The parser writes
+/// the AST again when only whitespace or comments changed, so it can be newer than the compile
+/// assets while they are still up to date. We don't touch the compile assets in that case, because
+/// their modification time tells whether the dependencies changed after the module was compiled.
+pub fn is_ast_compiled(ast_path: &str) -> bool {
+    match (
+        helpers::compute_file_hash(ast_path),
+        fs::read_to_string(helpers::get_ast_digest_path(ast_path)),
+    ) {
+        (Some(digest), Ok(compiled_digest)) => digest.to_hex().as_str() == compiled_digest,
+        _ => false,
+    }
+}
+
+/// Remembers that the compile assets are compiled from this AST, see `is_ast_compiled`.
+pub fn write_ast_digest(ast_path: &str) {
+    if let Some(digest) = helpers::compute_file_hash(ast_path) {
+        let _ = fs::write(helpers::get_ast_digest_path(ast_path), digest.to_hex().as_str());
+    }
+}
+
+/// The arguments for the parser that are the same for every file of a package. These are also part
+/// of the fingerprint of the package, so a change in these reparses the package.
+pub fn parser_args(
+    package: &packages::Package,
+    root_package: &packages::Package,
+    root_path: &str,
+    version: &Version,
+) -> Vec<String> {
+    let abs_node_modules_path = helpers::get_node_modules_path(root_path);
+
+    let ppx_flags = bsconfig::flatten_ppx_flags(
+        &abs_node_modules_path,
+        &filter_ppx_flags(&package.bsconfig.ppx_flags),
+        &package.package_dir,
+    );
+
+    let jsx_args = package.get_jsx_args(root_package);
+    let jsx_module_args = package.get_jsx_module_args(root_package);
+    let jsx_mode_args = package.get_jsx_mode_args(root_package);
+    let jsx_preserve_args = package.get_jsx_preserve_args(root_package);
+    let uncurried_args = package.get_uncurried_args(version, root_package);
+    let bsc_flags = bsconfig::flatten_flags(&package.bsconfig.bsc_flags);
+
+    [
+        vec!["-bs-v".to_string(), format!("{}", version)],
+        ppx_flags,
+        jsx_args,
+        jsx_module_args,
+        jsx_mode_args,
+        jsx_preserve_args,
+        uncurried_args,
+        bsc_flags,
+    ]
+    .concat()
+}
+
 fn generate_ast(
     package: packages::Package,
     root_package: packages::Package,
     filename: &str,
     root_path: &str,
-    version: &str,
+    compiler: &Compiler,
+    version: &Version,
 ) -> Result<(String, Option<String>), String> {
     let file = &filename.to_string();
-    let build_path_abs = helpers::get_build_path(root_path, &package.name, package.is_root);
+    let build_path_abs = helpers::get_build_path(&package.build_dir);
     let path = PathBuf::from(filename);
     let ast_extension = path_to_ast_extension(&path);
 
     let ast_path = (helpers::get_basename(&file.to_string()).to_owned()) + ast_extension;
-    let abs_node_modules_path = helpers::get_node_modules_path(root_path);
-
-    let ppx_flags = bsconfig::flatten_ppx_flags(
-        &abs_node_modules_path,
-        &filter_ppx_flags(&package.bsconfig.ppx_flags),
-        &package.name,
-    );
-
-    let jsx_args = root_package.get_jsx_args();
-    let jsx_module_args = root_package.get_jsx_module_args();
-    let jsx_mode_args = root_package.get_jsx_mode_args();
-    let uncurried_args = root_package.get_uncurried_args(version, &root_package);
-    let bsc_flags = bsconfig::flatten_flags(&package.bsconfig.bsc_flags);
+    let parser_args = parser_args(&package, &root_package, root_path, version);
 
     let res_to_ast_args = |file: &str| -> Vec<String> {
-        let file = "../../".to_string() + file;
+        // the build dir is not always inside of the package, see `Package::build_dir`. The path is
+        // canonicalized like the relative paths were, so it matches the source file locations
+        let package_dir =
+            helpers::canonicalize_string_path(&package.package_dir).unwrap_or(package.package_dir.to_owned());
+        let file = Path::new(&package_dir).join(file).to_string_lossy().to_string();
         vec![
-            vec!["-bs-v".to_string(), format!("{}", version)],
-            ppx_flags,
-            jsx_args,
-            jsx_module_args,
-            jsx_mode_args,
-            uncurried_args,
-            bsc_flags,
+            parser_args,
             vec![
                 "-absname".to_string(),
                 "-bs-ast".to_string(),
@@ -249,7 +319,8 @@ fn generate_ast(
 
     /* Create .ast */
     if let Some(res_to_ast) = Some(file).map(|file| {
-        Command::new(helpers::get_bsc(&root_path))
+        compiler
+            .command()
             .current_dir(helpers::canonicalize_string_path(&build_path_abs).unwrap())
             .args(res_to_ast_args(file))
             .output()
@@ -1,5 +1,6 @@
 use super::build_types::*;
 use super::packages;
+use crate::bsconfig;
 use crate::helpers;
 use ahash::{AHashMap, AHashSet};
 use std::fs;
@@ -10,6 +11,7 @@ pub fn read(build_state: &mut BuildState) -> CompileAssetsState {
     let mut ast_modules: AHashMap<String, AstModule> = AHashMap::new();
     let mut cmi_modules: AHashMap<String, SystemTime> = AHashMap::new();
     let mut cmt_modules: AHashMap<String, SystemTime> = AHashMap::new();
+    let mut cmj_modules: AHashMap<String, SystemTime> = AHashMap::new();
     let mut ast_rescript_file_locations = AHashSet::new();
 
     let mut rescript_file_locations = build_state
@@ -20,16 +22,12 @@ pub fn read(build_state: &mut BuildState) -> CompileAssetsState {
                 let package = build_state.packages.get(&module.package_name).unwrap();
 
                 Some(
-                    PathBuf::from(helpers::get_package_path(
-                        &build_state.project_root,
-                        &module.package_name,
-                        package.is_root,
-                    ))
-                    .canonicalize()
-                    .expect("Could not canonicalize")
-                    .join(source_file.implementation.path.to_owned())
-                    .to_string_lossy()
-                    .to_string(),
+                    PathBuf::from(&package.package_dir)
+                        .canonicalize()
+                        .expect("Could not canonicalize")
+                        .join(source_file.implementation.path.to_owned())
+                        .to_string_lossy()
+                        .to_string(),
                 )
             }
             _ => None,
@@ -43,16 +41,12 @@ pub fn read(build_state: &mut BuildState) -> CompileAssetsState {
             .filter_map(|module| {
                 let package = build_state.packages.get(&module.package_name).unwrap();
                 module.get_interface().as_ref().map(|interface| {
-                    PathBuf::from(helpers::get_package_path(
-                        &build_state.project_root,
-                        &module.package_name,
-                        package.is_root,
-                    ))
-                    .canonicalize()
-                    .expect("Could not canonicalize")
-                    .join(interface.path.to_owned())
-                    .to_string_lossy()
-                    .to_string()
+                    PathBuf::from(&package.package_dir)
+                        .canonicalize()
+                        .expect("Could not canonicalize")
+                        .join(interface.path.to_owned())
+                        .to_string_lossy()
+                        .to_string()
                 })
             })
             .collect::<AHashSet<String>>(),
@@ -60,12 +54,17 @@ pub fn read(build_state: &mut BuildState) -> CompileAssetsState {
 
     // scan all ast files in all packages
     for package in build_state.packages.values() {
-        let read_dir = fs::read_dir(std::path::Path::new(&helpers::get_build_path(
-            &build_state.project_root,
-            &package.name,
-            package.is_root,
-        )))
-        .unwrap();
+        let read_dir =
+            fs::read_dir(std::path::Path::new(&helpers::get_build_path(&package.build_dir))).unwrap();
+        let root_package = build_state
+            .packages
+            .get(&build_state.root_config_name)
+            .expect("Could not find root package");
+        // without a marker, the files were generated with the suffix that is configured now
+        let previous_suffix = fs::read_to_string(helpers::get_suffix_marker_path(&package.build_dir))
+            .ok()
+            .and_then(|suffix| bsconfig::Suffix::new(suffix.trim()).ok())
+            .unwrap_or(root_package.get_suffix());
 
         for entry in read_dir {
             match entry {
@@ -82,10 +81,6 @@ pub fn read(build_state: &mut BuildState) -> CompileAssetsState {
 
                                 let ast_file_path = path.to_str().unwrap().to_owned();
                                 let res_file_path = get_res_path_from_ast(&ast_file_path);
-                                let root_package = build_state
-                                    .packages
-                                    .get(&build_state.root_config_name)
-                                    .expect("Could not find root package");
                                 match res_file_path {
                                     Some(res_file_path) => {
                                         let _ = ast_modules.insert(
@@ -96,8 +91,9 @@ pub fn read(build_state: &mut BuildState) -> CompileAssetsState {
                                                 namespace: package.namespace.to_owned(),
                                                 last_modified: entry.metadata().unwrap().modified().unwrap(),
                                                 ast_file_path: ast_file_path,
-                                                is_root: package.is_root,
-                                                suffix: root_package.bsconfig.suffix.to_owned(),
+                                                package_dir: package.package_dir.to_owned(),
+                                                build_dir: package.build_dir.to_owned(),
+                                                suffix: previous_suffix.to_owned(),
                                             },
                                         );
                                         let _ = ast_rescript_file_locations.insert(res_file_path);
@@ -125,6 +121,14 @@ pub fn read(build_state: &mut BuildState) -> CompileAssetsState {
                                 cmt_modules
                                     .insert(module_name, entry.metadata().unwrap().modified().unwrap());
                             }
+                            "cmj" => {
+                                let module_name = helpers::file_path_to_module_name(
+                                    path.to_str().unwrap(),
+                                    &packages::Namespace::NoNamespace,
+                                );
+                                cmj_modules
+                                    .insert(module_name, entry.metadata().unwrap().modified().unwrap());
+                            }
                             _ => {
                                 // println!("other extension: {:?}", other);
                             }
@@ -141,6 +145,7 @@ pub fn read(build_state: &mut BuildState) -> CompileAssetsState {
         ast_modules,
         cmi_modules,
         cmt_modules,
+        cmj_modules,
         ast_rescript_file_locations,
         rescript_file_locations,
     }
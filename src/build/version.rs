@@ -0,0 +1,206 @@
+use super::compiler::Compiler;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A ReScript version, like `11.1.0` or `11.0.0-rc.4`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub prerelease: Option<String>,
+}
+
+impl Version {
+    /// Parses the version as it is printed by `bsc -v`, with or without the `ReScript` prefix.
+    /// Build metadata (after a `+`) is ignored.
+    pub fn parse(version: &str) -> Result<Self, String> {
+        let error = || format!("Could not read the ReScript version from `{}`", version.trim());
+        let trimmed = version.trim();
+        let trimmed = trimmed.strip_prefix("ReScript").unwrap_or(trimmed).trim();
+        let trimmed = trimmed.strip_prefix('v').unwrap_or(trimmed);
+        let trimmed = trimmed.split('+').next().unwrap_or(trimmed);
+        let (numbers, prerelease) = match trimmed.split_once('-') {
+            Some((numbers, prerelease)) if !prerelease.is_empty() => (numbers, Some(prerelease.to_string())),
+            Some(_) => return Err(error()),
+            None => (trimmed, None),
+        };
+        let numbers = numbers
+            .split('.')
+            .map(|number| number.parse::<u64>().map_err(|_| error()))
+            .collect::<Result<Vec<u64>, String>>()?;
+        match numbers[..] {
+            [major, minor, patch] => Ok(Version {
+                major,
+                minor,
+                patch,
+                prerelease,
+            }),
+            _ => Err(error()),
+        }
+    }
+
+    /// Whether this version of the compiler has the capability. Prereleases of a version already
+    /// have the capabilities of that version.
+    pub fn supports(&self, capability: Capability) -> bool {
+        (self.major, self.minor) >= capability.min_version()
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (&self.prerelease, &other.prerelease) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(prerelease), Some(other_prerelease)) => {
+                    compare_prereleases(prerelease, other_prerelease)
+                }
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Compares the dot separated identifiers of the prereleases like semver does: numbers are compared
+/// numerically and are lower than other identifiers, so `rc.4` < `rc.10`.
+fn compare_prereleases(prerelease: &str, other_prerelease: &str) -> Ordering {
+    let identifiers = prerelease.split('.');
+    let other_identifiers = other_prerelease.split('.');
+    for (identifier, other_identifier) in identifiers.clone().zip(other_identifiers.clone()) {
+        let ordering = match (identifier.parse::<u64>(), other_identifier.parse::<u64>()) {
+            (Ok(number), Ok(other_number)) => number.cmp(&other_number),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => identifier.cmp(other_identifier),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    identifiers.count().cmp(&other_identifiers.count())
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        match &self.prerelease {
+            Some(prerelease) => write!(f, "-{}", prerelease),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The features that depend on the version of the compiler. All the flags and config options that
+/// only work with some versions are checked against this table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// Modules are compiled in uncurried mode, unless `uncurried` is turned off
+    UncurriedByDefault,
+    /// JSX version 4 with a custom `jsx.module`, like Preact
+    GenericJsxModule,
+    /// `jsx.preserve`, which keeps the JSX in the generated JavaScript
+    JsxPreserve,
+    /// Suffixes other than `.js`, `.mjs`, `.cjs` and their `.bs` variants, like `.res.mjs`
+    CustomSuffix,
+}
+
+impl Capability {
+    /// The first (major, minor) version of ReScript that has the capability
+    pub fn min_version(&self) -> (u64, u64) {
+        match self {
+            Capability::UncurriedByDefault => (11, 0),
+            Capability::GenericJsxModule => (11, 1),
+            Capability::JsxPreserve => (12, 0),
+            Capability::CustomSuffix => (11, 0),
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Capability::UncurriedByDefault => "uncurried mode",
+            Capability::GenericJsxModule => "a custom JSX module",
+            Capability::JsxPreserve => "JSX preserve mode",
+            Capability::CustomSuffix => "a custom suffix",
+        }
+    }
+
+    /// The error for when a config option needs this capability, but the compiler is too old.
+    pub fn unsupported_error(&self, version: &Version) -> String {
+        let (major, minor) = self.min_version();
+        format!(
+            "{} needs ReScript {}.{} or higher, but the version is {}",
+            self.description(),
+            major,
+            minor,
+            version
+        )
+    }
+}
+
+/// Asks the compiler for its version. This is done once per build, all the version dependent
+/// flags are derived from it.
+pub fn get_version(compiler: &Compiler) -> Result<Version, String> {
+    let output = compiler.command().arg("-v").output().map_err(|e| {
+        format!(
+            "Could not run {} to get the ReScript version: {}",
+            compiler.bsc, e
+        )
+    })?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Version::parse(&stdout)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Capability, Version};
+
+    fn version(version: &str) -> Version {
+        Version::parse(version).unwrap()
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            version("ReScript 11.0.0-rc.4\n"),
+            Version {
+                major: 11,
+                minor: 0,
+                patch: 0,
+                prerelease: Some("rc.4".to_string())
+            }
+        );
+        assert_eq!(version("10.1.4").to_string(), "10.1.4");
+        assert_eq!(version("12.0.0-alpha.1+build.5").to_string(), "12.0.0-alpha.1");
+        assert!(Version::parse("").is_err());
+        assert!(Version::parse("ReScript 11").is_err());
+        assert!(Version::parse("11.0.0-").is_err());
+        assert!(Version::parse("command not found").is_err());
+    }
+
+    #[test]
+    fn test_versions_are_ordered_like_semver() {
+        assert!(version("11.0.0-rc.4") < version("11.0.0-rc.10"));
+        assert!(version("11.0.0-rc.10") < version("11.0.0"));
+        assert!(version("11.0.0-alpha") < version("11.0.0-alpha.1"));
+        assert!(version("11.0.0-alpha.1") < version("11.0.0-beta"));
+        assert!(version("10.1.4") < version("11.0.0-alpha.1"));
+        assert!(version("11.1.0") < version("11.10.0"));
+    }
+
+    #[test]
+    fn test_capabilities() {
+        assert!(!version("10.1.4").supports(Capability::UncurriedByDefault));
+        assert!(version("11.0.0-rc.4").supports(Capability::UncurriedByDefault));
+        assert!(!version("11.0.1").supports(Capability::GenericJsxModule));
+        assert!(version("11.1.0").supports(Capability::GenericJsxModule));
+        assert!(!version("11.1.0").supports(Capability::JsxPreserve));
+        assert!(version("12.0.0-alpha.1").supports(Capability::JsxPreserve));
+    }
+}
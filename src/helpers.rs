@@ -43,39 +43,43 @@ impl LexicalAbsolute for Path {
     }
 }
 
-pub fn get_relative_package_path(package_name: &str, is_root: bool) -> String {
-    match is_root {
-        true => "".to_string(),
-        false => format!("node_modules/{}", package_name),
-    }
+/// The build paths are relative to the build dir of the package. That is the directory of the
+/// package itself, unless the package is built out of tree (see `packages::Package::build_dir`).
+pub fn get_build_path(build_dir: &str) -> String {
+    format!("{}/lib/ocaml", build_dir)
 }
 
-pub fn get_package_path(root: &str, package_name: &str, is_root: bool) -> String {
-    match is_root {
-        true => root.to_string(),
-        false => format!("{}/node_modules/{}", root, package_name),
-    }
+pub fn get_bs_build_path(build_dir: &str) -> String {
+    format!("{}/lib/bs", build_dir)
 }
 
-pub fn get_build_path(root: &str, package_name: &str, is_root: bool) -> String {
-    match is_root {
-        true => format!("{}/lib/ocaml", root),
-        false => format!("{}/node_modules/{}/lib/ocaml", root, package_name),
-    }
+/// The file in which we keep the suffix that the JavaScript files of a package were last
+/// generated with, so we can remove the old files when the suffix changes.
+pub fn get_suffix_marker_path(build_dir: &str) -> String {
+    get_bs_build_path(build_dir) + "/.suffix"
 }
 
-pub fn get_bs_build_path(root: &str, package_name: &str, is_root: bool) -> String {
-    match is_root {
-        true => format!("{}/lib/bs", root),
-        false => format!("{}/node_modules/{}/lib/bs", root, package_name),
-    }
+/// The file in which we keep the fingerprint of the settings a package was last built with, see
+/// `build::fingerprint`.
+pub fn get_fingerprint_path(build_dir: &str) -> String {
+    get_bs_build_path(build_dir) + "/.fingerprint"
 }
 
-pub fn get_path(root: &str, package_name: &str, file: &str, is_root: bool) -> String {
-    match is_root {
-        true => format!("{}/{}", root, file),
-        false => format!("{}/node_modules/{}/{}", root, package_name, file),
-    }
+/// The file in which we keep the source files of a prebuilt dependency, see
+/// `packages::write_prebuilt_manifests`.
+pub fn get_prebuilt_manifest_path(build_dir: &str) -> String {
+    get_bs_build_path(build_dir) + "/.prebuilt"
+}
+
+/// Looks for `node_modules/<path>` the way Node does: in the given directory first, and then in each
+/// of its parents. Directories that are a `node_modules` folder themselves are skipped, so a
+/// package inside a `node_modules` folder finds its siblings.
+pub fn find_in_node_modules(path: &str, from_dir: &str) -> Option<PathBuf> {
+    Path::new(from_dir)
+        .ancestors()
+        .filter(|dir| dir.file_name().map(|name| name != "node_modules").unwrap_or(true))
+        .map(|dir| dir.join("node_modules").join(path))
+        .find(|path| path.exists())
 }
 
 pub fn get_node_modules_path(root: &str) -> String {
@@ -164,18 +168,6 @@ pub fn create_build_path(build_path: &str) {
         .unwrap();
 }
 
-pub fn get_bsc(root_path: &str) -> String {
-    let subfolder = match (std::env::consts::OS, std::env::consts::ARCH) {
-        ("macos", "aarch64") => "darwinarm64",
-        ("macos", _) => "darwin",
-        ("linux", _) => "linux",
-        ("windows", _) => "win32",
-        _ => panic!("Unsupported architecture"),
-    };
-
-    get_node_modules_path(root_path) + "/rescript/" + subfolder + "/bsc.exe"
-}
-
 pub fn string_ends_with_any(s: &PathBuf, suffixes: &[&str]) -> bool {
     suffixes
         .iter()
@@ -184,13 +176,11 @@ pub fn string_ends_with_any(s: &PathBuf, suffixes: &[&str]) -> bool {
 
 pub fn get_compiler_asset(
     source_file: &str,
-    package_name: &str,
     namespace: &packages::Namespace,
-    root_path: &str,
+    build_dir: &str,
     extension: &str,
-    is_root: bool,
 ) -> String {
-    get_build_path(root_path, package_name, is_root)
+    get_build_path(build_dir)
         + "/"
         + &file_path_to_compiler_asset_basename(source_file, namespace)
         + "."
@@ -206,11 +196,9 @@ pub fn canonicalize_string_path(path: &str) -> Option<String> {
 
 pub fn get_bs_compiler_asset(
     source_file: &str,
-    package_name: &str,
     namespace: &packages::Namespace,
-    root_path: &str,
+    build_dir: &str,
     extension: &str,
-    is_root: bool,
 ) -> String {
     let namespace = match extension {
         "ast" | "iast" => &packages::Namespace::NoNamespace,
@@ -219,7 +207,7 @@ pub fn get_bs_compiler_asset(
 
     let dir = std::path::Path::new(&source_file).parent().unwrap();
 
-    std::path::Path::new(&get_bs_build_path(root_path, &package_name, is_root))
+    std::path::Path::new(&get_bs_build_path(build_dir))
         .join(dir)
         .join(file_path_to_compiler_asset_basename(source_file, namespace) + extension)
         .to_str()
@@ -237,34 +225,26 @@ pub fn is_interface_ast_file(file: &str) -> bool {
     file.ends_with(".iast")
 }
 
-pub fn get_mlmap_path(root_path: &str, package_name: &str, namespace: &str, is_root: bool) -> String {
-    get_build_path(root_path, package_name, is_root) + "/" + namespace + ".mlmap"
+pub fn get_mlmap_path(build_dir: &str, namespace: &str) -> String {
+    get_build_path(build_dir) + "/" + namespace + ".mlmap"
+}
+
+pub fn get_mlmap_compile_path(build_dir: &str, namespace: &str) -> String {
+    get_build_path(build_dir) + "/" + namespace + ".cmi"
 }
 
-pub fn get_mlmap_compile_path(root_path: &str, package_name: &str, namespace: &str, is_root: bool) -> String {
-    get_build_path(root_path, package_name, is_root) + "/" + namespace + ".cmi"
+pub fn get_ast_path(source_file: &str, build_dir: &str) -> String {
+    get_compiler_asset(source_file, &packages::Namespace::NoNamespace, build_dir, "ast")
 }
 
-pub fn get_ast_path(source_file: &str, package_name: &str, root_path: &str, is_root: bool) -> String {
-    get_compiler_asset(
-        source_file,
-        package_name,
-        &packages::Namespace::NoNamespace,
-        root_path,
-        "ast",
-        is_root,
-    )
+pub fn get_iast_path(source_file: &str, build_dir: &str) -> String {
+    get_compiler_asset(source_file, &packages::Namespace::NoNamespace, build_dir, "iast")
 }
 
-pub fn get_iast_path(source_file: &str, package_name: &str, root_path: &str, is_root: bool) -> String {
-    get_compiler_asset(
-        source_file,
-        package_name,
-        &packages::Namespace::NoNamespace,
-        root_path,
-        "iast",
-        is_root,
-    )
+/// The file in which we keep the digest of the AST that the compile assets were last compiled from,
+/// see `parse::is_ast_compiled`.
+pub fn get_ast_digest_path(ast_path: &str) -> String {
+    ast_path.to_string() + ".digest"
 }
 
 pub fn read_lines(filename: String) -> io::Result<io::Lines<io::BufReader<fs::File>>> {
@@ -1,5 +1,7 @@
 use clap::{Parser, ValueEnum};
+use console::style;
 use regex::Regex;
+use std::sync::atomic::AtomicBool;
 
 pub mod bsconfig;
 pub mod build;
@@ -28,6 +30,8 @@ struct Args {
     command: Option<Command>,
 
     /// The relative path to where the main bsconfig.json resides. IE - the root of your project.
+    /// Defaults to the root of the project that the current directory is in. When that is one of
+    /// the packages of a workspace, only that package and its dependencies are built.
     folder: Option<String>,
 
     /// Filter allows for a regex to be supplied which will filter the files to be compiled. For
@@ -44,6 +48,40 @@ struct Args {
 
     #[arg(short, long)]
     no_timing: Option<bool>,
+
+    /// The maximum number of compiler processes that run at the same time. Defaults to the number
+    /// of cores. Can also be set with the REWATCH_JOBS environment variable, or with `jobs` in the
+    /// `rewatch` section of the root bsconfig.json.
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// The maximum number of compiler processes that run at the same time in watch mode, so the
+    /// editor stays responsive while compiling. Defaults to the jobs setting, or half of the cores.
+    /// Can also be set with the REWATCH_WATCH_JOBS environment variable, or with `watch-jobs` in the
+    /// `rewatch` section of the root bsconfig.json.
+    #[arg(long)]
+    watch_jobs: Option<usize>,
+
+    /// Keep going after errors. All files are parsed, and every module of which the dependencies
+    /// compiled successfully is compiled, so all errors are shown at once. The modules that are
+    /// blocked by a failed dependency are reported.
+    #[arg(short, long)]
+    keep_going: bool,
+
+    /// The path to the bsc binary, for custom toolchains or a compiler that is not installed in
+    /// node_modules. Can also be set with the RESCRIPT_BSC_EXE environment variable, or with `bsc`
+    /// in the `rewatch` section of the root bsconfig.json. A relative path here or in the
+    /// environment variable is relative to the current directory, in the config it is relative to
+    /// the root.
+    #[arg(long)]
+    bsc: Option<String>,
+
+    /// A command that every invocation of bsc is wrapped in, like `ccache` for C compilers. Its
+    /// arguments are separated by spaces. Can also be set with the REWATCH_LAUNCHER environment
+    /// variable, or with `launcher` in the `rewatch` section of the root bsconfig.json, as an array
+    /// of the command and its arguments.
+    #[arg(long)]
+    launcher: Option<String>,
 }
 
 fn main() {
@@ -51,15 +89,70 @@ fn main() {
     let args = Args::parse();
 
     let command = args.command.unwrap_or(Command::Build);
-    let folder = args.folder.unwrap_or(".".to_string());
+    let (folder, focus) = match args.folder {
+        Some(folder) => (folder, None),
+        None => match build::packages::find_project_root(".") {
+            Ok(root) => root,
+            Err(e) => {
+                println!("{}", style(e).red());
+                std::process::exit(1)
+            }
+        },
+    };
     let filter = args
         .filter
         .map(|filter| Regex::new(filter.as_ref()).expect("Could not parse regex"));
 
+    let root_config = match build::packages::read_bsconfig(&helpers::get_abs_path(&folder)) {
+        Ok(root_config) => root_config,
+        Err(e) => {
+            println!("{}", style(e).red());
+            std::process::exit(1)
+        }
+    };
     match command {
-        Command::Clean => build::clean::clean(&folder),
+        Command::Watch => build::jobs::initialize_thread_pool(build::jobs::get_watch_jobs(
+            args.watch_jobs,
+            args.jobs,
+            &root_config,
+        )),
+        _ => {
+            if let Some(jobs) = build::jobs::get_jobs(args.jobs, &root_config) {
+                build::jobs::initialize_thread_pool(jobs)
+            }
+        }
+    }
+
+    let get_compiler = || match build::compiler::get_compiler(
+        args.bsc.to_owned(),
+        args.launcher.to_owned(),
+        &root_config,
+        &helpers::get_abs_path(&folder),
+    ) {
+        Ok(compiler) => compiler,
+        Err(e) => {
+            println!("{}", style(e).red());
+            std::process::exit(1)
+        }
+    };
+
+    match command {
+        Command::Clean => {
+            if let Err(e) = build::clean::clean(&folder) {
+                println!("{}", style(e).red());
+                std::process::exit(1)
+            }
+        }
         Command::Build => {
-            match build::build(&filter, &folder, args.no_timing.unwrap_or(false)) {
+            match build::build(
+                &filter,
+                &folder,
+                &focus,
+                &get_compiler(),
+                args.no_timing.unwrap_or(false),
+                args.keep_going,
+                &AtomicBool::new(false),
+            ) {
                 Err(()) => std::process::exit(1),
                 Ok(_) => {
                     args.after_build.map(|command| cmd::run(command));
@@ -68,9 +161,29 @@ fn main() {
             };
         }
         Command::Watch => {
-            let _initial_build = build::build(&filter, &folder, false);
+            let compiler = get_compiler();
+            let initial_build = build::build(
+                &filter,
+                &folder,
+                &focus,
+                &compiler,
+                false,
+                args.keep_going,
+                &AtomicBool::new(false),
+            );
             args.after_build.clone().map(|command| cmd::run(command));
-            watcher::start(&filter, &folder, args.after_build);
+            let prebuilt_dirs = initial_build
+                .map(|build_state| build::packages::get_prebuilt_dependency_dirs(&build_state.packages))
+                .unwrap_or_default();
+            watcher::start(
+                &filter,
+                &folder,
+                &focus,
+                &compiler,
+                args.after_build,
+                args.keep_going,
+                prebuilt_dirs,
+            );
         }
     }
 }
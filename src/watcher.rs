@@ -1,19 +1,99 @@
 use crate::build;
+use crate::build::compiler::Compiler;
+use crate::build::packages;
 use crate::cmd;
 use crate::helpers;
 use crate::queue::FifoQueue;
 use crate::queue::*;
 use futures_timer::Delay;
 use notify::{Config, Error, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 use std::time::Duration;
 
+fn needs_compile(event: &Event, filter: &Option<regex::Regex>, prebuilt_dirs: &[String]) -> bool {
+    event.paths.iter().any(|path| {
+        let path_buf = path.to_path_buf();
+
+        let is_in_bs_build_path = path_buf
+            .to_str()
+            .map(|x| x.contains("/lib/bs/") || x.contains("/lib/ocaml/"))
+            .unwrap_or(false);
+
+        // the build doesn't read the sources of the prebuilt dependencies once they are built. Their
+        // directories are canonical, while the path can go through a symlink in node_modules
+        let canonical_path = path_buf.canonicalize().unwrap_or_else(|_| path_buf.to_owned());
+        let is_in_prebuilt_dependency = prebuilt_dirs
+            .iter()
+            .any(|dir| canonical_path.starts_with(dir) || path_buf.starts_with(dir));
+
+        let name = path_buf
+            .file_name()
+            .and_then(|x| x.to_str())
+            .unwrap_or("Unknown")
+            .to_string();
+
+        let extension = path_buf.extension().and_then(|ext| ext.to_str());
+        match (is_in_bs_build_path || is_in_prebuilt_dependency, extension) {
+            (false, Some(extension)) => {
+                (helpers::is_implementation_file(&extension) || helpers::is_interface_file(&extension))
+                    && filter.as_ref().map(|re| !re.is_match(&name)).unwrap_or(true)
+            }
+
+            _ => false,
+        }
+    })
+}
+
+/// A build that runs on its own thread, so the watcher can keep receiving events and cancel the
+/// build when files change while it is running.
+struct RunningBuild {
+    /// Returns the directories of the prebuilt dependencies when the build succeeded
+    handle: JoinHandle<Result<Vec<String>, ()>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Everything the watcher needs to start a build.
+#[derive(Clone)]
+struct BuildOptions {
+    filter: Option<regex::Regex>,
+    path: String,
+    focus: Option<String>,
+    compiler: Compiler,
+    keep_going: bool,
+}
+
+fn start_build(options: &BuildOptions) -> RunningBuild {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let build_cancelled = cancelled.clone();
+    let options = options.clone();
+    let handle = thread::spawn(move || {
+        build::build(
+            &options.filter,
+            &options.path,
+            &options.focus,
+            &options.compiler,
+            false,
+            options.keep_going,
+            &build_cancelled,
+        )
+        .map(|build_state| packages::get_prebuilt_dependency_dirs(&build_state.packages))
+    });
+    RunningBuild { handle, cancelled }
+}
+
 async fn async_watch(
     q: Arc<FifoQueue<Result<Event, Error>>>,
-    path: &str,
-    filter: &Option<regex::Regex>,
+    options: &BuildOptions,
     after_build: Option<String>,
+    mut prebuilt_dirs: Vec<String>,
 ) -> notify::Result<()> {
+    let mut running_build: Option<RunningBuild> = None;
+    // when files change while building, we cancel the running build and queue up a single new
+    // build that starts as soon as the running one has stopped
+    let mut pending = false;
+
     loop {
         // We want to sleep for a little while so the CPU can schedule other work. That way we end
         // up not burning CPU cycles.
@@ -26,35 +106,30 @@ async fn async_watch(
             }
         }
 
-        let needs_compile = events.iter().any(|event| {
-            event.paths.iter().any(|path| {
-                let path_buf = path.to_path_buf();
-
-                let is_in_bs_build_path = path_buf
-                    .to_str()
-                    .map(|x| x.contains("/lib/bs/") || x.contains("/lib/ocaml/"))
-                    .unwrap_or(false);
-
-                let name = path_buf
-                    .file_name()
-                    .and_then(|x| x.to_str())
-                    .unwrap_or("Unknown")
-                    .to_string();
-
-                let extension = path_buf.extension().and_then(|ext| ext.to_str());
-                match (is_in_bs_build_path, extension) {
-                    (false, Some(extension)) => {
-                        (helpers::is_implementation_file(&extension)
-                            || helpers::is_interface_file(&extension))
-                            && filter.as_ref().map(|re| !re.is_match(&name)).unwrap_or(true)
-                    }
-
-                    _ => false,
-                }
-            })
-        });
-
-        if needs_compile {
+        if events
+            .iter()
+            .any(|event| needs_compile(event, &options.filter, &prebuilt_dirs))
+        {
+            pending = true;
+            if let Some(running_build) = &running_build {
+                running_build.cancelled.store(true, Ordering::Relaxed);
+            }
+        }
+
+        if let Some(RunningBuild { handle, cancelled }) = running_build.take() {
+            if !handle.is_finished() {
+                running_build = Some(RunningBuild { handle, cancelled });
+                continue;
+            }
+            if let Ok(Ok(build_prebuilt_dirs)) = handle.join() {
+                prebuilt_dirs = build_prebuilt_dirs;
+            }
+            if !cancelled.load(Ordering::Relaxed) {
+                after_build.clone().map(|command| cmd::run(command));
+            }
+        }
+
+        if pending {
             // Wait for events to settle
             Delay::new(Duration::from_millis(300)).await;
 
@@ -63,13 +138,21 @@ async fn async_watch(
                 let _ = q.pop();
             }
 
-            let _ = build::build(filter, path, false);
-            after_build.clone().map(|command| cmd::run(command));
+            pending = false;
+            running_build = Some(start_build(options));
         }
     }
 }
 
-pub fn start(filter: &Option<regex::Regex>, folder: &str, after_build: Option<String>) {
+pub fn start(
+    filter: &Option<regex::Regex>,
+    folder: &str,
+    focus: &Option<String>,
+    compiler: &Compiler,
+    after_build: Option<String>,
+    keep_going: bool,
+    prebuilt_dirs: Vec<String>,
+) {
     futures::executor::block_on(async {
         let queue = Arc::new(FifoQueue::<Result<Event, Error>>::new());
         let producer = queue.clone();
@@ -81,7 +164,14 @@ pub fn start(filter: &Option<regex::Regex>, folder: &str, after_build: Option<St
             .watch(folder.as_ref(), RecursiveMode::Recursive)
             .expect("Could not start watcher");
 
-        if let Err(e) = async_watch(consumer, folder, filter, after_build).await {
+        let options = BuildOptions {
+            filter: filter.to_owned(),
+            path: folder.to_owned(),
+            focus: focus.to_owned(),
+            compiler: compiler.to_owned(),
+            keep_going,
+        };
+        if let Err(e) = async_watch(consumer, &options, after_build, prebuilt_dirs).await {
             println!("error: {:?}", e)
         }
     })